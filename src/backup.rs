@@ -0,0 +1,408 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+use fs_extra::dir::get_dir_content;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::consensus::ConsensusKind;
+use crate::detect::ensure_v6_1_0;
+use crate::manifest;
+use crate::manifest::FileEntry;
+use crate::migrate::load_node_dirs;
+use crate::migrate::NodeConfigMigrate;
+use crate::migrate::NodeDirs;
+use crate::network::NetworkKind;
+use crate::plan::ensure_enough_space;
+use crate::plan::human_size;
+use crate::running;
+use crate::staging;
+use crate::staging::OnConflict;
+use crate::transfer::free_space;
+
+// The last entry of a backup archive.
+pub const BACKUP_MANIFEST_FILE: &str = "backup-manifest.toml";
+
+#[derive(Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub chain_name: String,
+    // Where the backup was taken.
+    pub chain_dir: PathBuf,
+    // UTC, e.g. `20211001T120000Z`.
+    pub created_at: String,
+    // Top level dirs of the archive, the metadata dir and the node dirs.
+    pub dirs: Vec<PathBuf>,
+    // Relative to the chain dir.
+    pub files: Vec<FileEntry>,
+}
+
+// Snapshot the metadata dir and the node dirs of the old chain into a timestamped tar
+// archive in `backup_dir`, return the archive path.
+pub fn backup<P, Q>(
+    chain_data_dir: P,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+    backup_dir: Q,
+    force: bool,
+) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let chain_data_dir = chain_data_dir.as_ref();
+    let backup_dir = backup_dir.as_ref();
+
+    ensure_v6_1_0(chain_data_dir, chain_name, explicit_node_dirs)?;
+    let NodeDirs {
+        dirs: node_dirs,
+        skipped,
+    } = load_node_dirs(chain_data_dir, chain_name, explicit_node_dirs)?;
+    for (d, reason) in skipped {
+        eprintln!("warning: skip dir `{}`: {}", d.to_string_lossy(), reason);
+    }
+    ensure!(!node_dirs.is_empty(), "Empty chain. No node dir found");
+
+    // The RocksDB of a running node can't be snapshotted consistently.
+    let configs = node_dirs
+        .iter()
+        .map(|d| {
+            NodeConfigMigrate::extract_from(d)
+                .map(|node| node.generate_new(ConsensusKind::default(), NetworkKind::default()))
+                .with_context(|| {
+                    format!(
+                        "cannot extract info from old node config in `{}`",
+                        d.to_string_lossy()
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    running::ensure_stopped(node_dirs.iter().map(PathBuf::as_path).zip(&configs), force)?;
+
+    let meta_dir = chain_data_dir.join(chain_name);
+    ensure!(
+        meta_dir.is_dir(),
+        "chain metadata dir `{}` not found",
+        meta_dir.to_string_lossy()
+    );
+    let mut dirs = vec![meta_dir];
+    dirs.extend(node_dirs);
+
+    let mut total_size = 0;
+    for d in &dirs {
+        total_size += get_dir_content(d)
+            .with_context(|| format!("cannot get size of dir `{}`", d.to_string_lossy()))?
+            .dir_size;
+    }
+    fs::create_dir_all(backup_dir)
+        .with_context(|| format!("cannot create dir `{}`", backup_dir.to_string_lossy()))?;
+    ensure_enough_space(total_size, free_space(backup_dir)?, backup_dir)?;
+
+    let created_at = utc_timestamp(SystemTime::now());
+    let archive = backup_dir.join(format!("{}-{}.tar", chain_name, created_at));
+    ensure!(
+        !archive.exists(),
+        "archive `{}` already exists",
+        archive.to_string_lossy()
+    );
+    let mut manifest = BackupManifest {
+        chain_name: chain_name.to_string(),
+        chain_dir: chain_data_dir.to_path_buf(),
+        created_at,
+        dirs: vec![],
+        files: vec![],
+    };
+
+    // Written aside and renamed, so an archive is never half written.
+    let tmp = archive.with_extension("tar.tmp");
+    if let Err(e) = write_archive(&tmp, &dirs, &mut manifest) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, &archive)
+        .with_context(|| format!("cannot write archive `{}`", archive.to_string_lossy()))?;
+
+    println!(
+        "backup of chain `{}` written to `{}`: {} dir(s), {} file(s), {}",
+        chain_name,
+        archive.to_string_lossy(),
+        manifest.dirs.len(),
+        manifest.files.len(),
+        human_size(manifest.files.iter().map(|f| f.size).sum())
+    );
+    Ok(archive)
+}
+
+fn write_archive(archive: &Path, dirs: &[PathBuf], manifest: &mut BackupManifest) -> Result<()> {
+    let file = File::create(archive)
+        .with_context(|| format!("cannot create archive `{}`", archive.to_string_lossy()))?;
+    let mut builder = tar::Builder::new(file);
+    builder.follow_symlinks(false);
+
+    for d in dirs {
+        let name = PathBuf::from(d.file_name().with_context(|| {
+            format!(
+                "invalid dir `{}`, must end with a dir name",
+                d.to_string_lossy()
+            )
+        })?);
+        ensure!(
+            !manifest.dirs.contains(&name),
+            "more than one dir named `{}` to back up",
+            name.to_string_lossy()
+        );
+        append_dir(&mut builder, d, &name, &mut manifest.files)
+            .with_context(|| format!("cannot archive dir `{}`", d.to_string_lossy()))?;
+        manifest.dirs.push(name);
+    }
+
+    let content = toml::to_string_pretty(manifest).context("cannot serialize backup manifest")?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    );
+    builder
+        .append_data(&mut header, BACKUP_MANIFEST_FILE, content.as_bytes())
+        .context("cannot write backup manifest")?;
+    builder
+        .into_inner()
+        .and_then(|file| file.sync_all())
+        .with_context(|| format!("cannot write archive `{}`", archive.to_string_lossy()))
+}
+
+// Archive `dir` as `name`, hashing every file on the way.
+fn append_dir(
+    builder: &mut tar::Builder<File>,
+    dir: &Path,
+    name: &Path,
+    files: &mut Vec<FileEntry>,
+) -> Result<()> {
+    builder.append_dir(name, dir)?;
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|ent| ent.file_name());
+    for ent in entries {
+        let path = ent.path();
+        let rel = name.join(ent.file_name());
+        let file_type = ent.file_type()?;
+        if file_type.is_dir() {
+            append_dir(builder, &path, &rel, files)?;
+        } else if file_type.is_file() {
+            let meta = ent.metadata()?;
+            let mut header = tar::Header::new_gnu();
+            header.set_metadata(&meta);
+            let mut reader = HashingReader {
+                inner: File::open(&path)?,
+                hasher: Sha256::new(),
+            };
+            builder.append_data(&mut header, &rel, &mut reader)?;
+            files.push(FileEntry {
+                path: rel,
+                size: meta.len(),
+                sha256: hex::encode(reader.hasher.finalize()),
+            });
+        } else {
+            // e.g. symlinks, kept as they are.
+            builder.append_path_with_name(&path, &rel)?;
+        }
+    }
+    Ok(())
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// Put the dirs in a backup archive back into `chain_data_dir`.
+pub fn restore<P, Q>(archive: P, chain_data_dir: Q, on_conflict: OnConflict) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let archive = archive.as_ref();
+    let chain_data_dir = chain_data_dir.as_ref();
+
+    // Extracted aside and checked first, so a bad archive leaves the chain dir untouched.
+    let staging_dir = staging::staging_dir(chain_data_dir)?;
+    ensure!(
+        !staging_dir.exists(),
+        "staging dir `{}` already exists, remove it first",
+        staging_dir.to_string_lossy()
+    );
+    let manifest = match extract(archive, &staging_dir).and_then(|manifest| {
+        let conflicts =
+            staging::conflicts(chain_data_dir, manifest.dirs.iter().map(PathBuf::as_path));
+        if on_conflict == OnConflict::Fail {
+            staging::ensure_no_conflicts(&conflicts)?;
+        }
+        for d in &conflicts {
+            match on_conflict {
+                OnConflict::Overwrite => {
+                    eprintln!("warning: `{}` will be overwritten", d.to_string_lossy())
+                }
+                OnConflict::Skip => println!("`{}` already exists, skip", d.to_string_lossy()),
+                OnConflict::Fail => unreachable!(),
+            }
+        }
+        Ok(manifest)
+    }) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            staging::discard(&staging_dir, None);
+            return Err(e);
+        }
+    };
+    staging::commit(&staging_dir, chain_data_dir, on_conflict)?;
+
+    println!(
+        "chain `{}` restored to `{}` from the backup taken at {}",
+        manifest.chain_name,
+        chain_data_dir.to_string_lossy(),
+        manifest.created_at
+    );
+    Ok(())
+}
+
+// Extract the archive into `dir` and check it against its manifest.
+fn extract(archive: &Path, dir: &Path) -> Result<BackupManifest> {
+    let file = File::open(archive)
+        .with_context(|| format!("cannot open archive `{}`", archive.to_string_lossy()))?;
+    fs::create_dir_all(dir)
+        .with_context(|| format!("cannot create dir `{}`", dir.to_string_lossy()))?;
+    let read_err = || format!("cannot read archive `{}`", archive.to_string_lossy());
+
+    let mut tar = tar::Archive::new(file);
+    tar.set_preserve_permissions(true);
+    tar.set_preserve_mtime(true);
+    let mut manifest = None;
+    for entry in tar.entries().with_context(read_err)? {
+        let mut entry = entry.with_context(read_err)?;
+        let path = entry.path().with_context(read_err)?.into_owned();
+        if path == Path::new(BACKUP_MANIFEST_FILE) {
+            let mut content = String::new();
+            entry.read_to_string(&mut content).with_context(read_err)?;
+            manifest = Some(
+                toml::from_str::<BackupManifest>(&content).context("invalid backup manifest")?,
+            );
+        } else {
+            let unpacked = entry
+                .unpack_in(dir)
+                .with_context(|| format!("cannot extract `{}`", path.to_string_lossy()))?;
+            ensure!(
+                unpacked,
+                "archive entry `{}` points outside of the chain dir",
+                path.to_string_lossy()
+            );
+        }
+    }
+    let manifest = manifest.with_context(|| {
+        format!(
+            "no `{}` in `{}`, not a backup made by this tool",
+            BACKUP_MANIFEST_FILE,
+            archive.to_string_lossy()
+        )
+    })?;
+
+    let problems = manifest::check(dir, &manifest.files);
+    if !problems.is_empty() {
+        let list: Vec<String> = problems
+            .iter()
+            .map(|(f, problem)| format!("  `{}`: {}", f.to_string_lossy(), problem))
+            .collect();
+        bail!(
+            "archive `{}` doesn't match its manifest:\n{}",
+            archive.to_string_lossy(),
+            list.join("\n")
+        );
+    }
+    Ok(manifest)
+}
+
+// Like `20211001T120000Z` for the UTC time of `t`.
+fn utc_timestamp(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> String {
+        utc_timestamp(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn timestamp_of_the_epoch() {
+        assert_eq!(at(0), "19700101T000000Z");
+    }
+
+    #[test]
+    fn timestamp_of_a_plain_date() {
+        assert_eq!(at(1633089600), "20211001T120000Z");
+        assert_eq!(at(1735689599), "20241231T235959Z");
+    }
+
+    #[test]
+    fn timestamp_around_leap_days() {
+        assert_eq!(at(1583020799), "20200229T235959Z");
+        assert_eq!(at(1583020800), "20200301T000000Z");
+        // Every 400 years is a leap year, other centuries are not.
+        assert_eq!(at(951782400), "20000229T000000Z");
+        assert_eq!(at(4107456000), "21000228T000000Z");
+        assert_eq!(at(4107542400), "21000301T000000Z");
+    }
+
+    #[test]
+    fn timestamp_before_the_epoch_is_the_epoch() {
+        assert_eq!(
+            utc_timestamp(UNIX_EPOCH - Duration::from_secs(1)),
+            "19700101T000000Z"
+        );
+    }
+}
@@ -1,3 +1,8 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use anyhow::Result;
+
 use rcgen::BasicConstraints;
 use rcgen::Certificate;
 use rcgen::CertificateParams;
@@ -5,6 +10,10 @@ use rcgen::IsCa;
 use rcgen::KeyPair;
 use rcgen::PKCS_ECDSA_P256_SHA256;
 
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CertAndKey {
     pub cert: String,
     pub key: String,
@@ -49,12 +58,61 @@ fn cert(domain: &str, signer: &Certificate) -> (Certificate, CertAndKey) {
     (cert, cert_and_key)
 }
 
-pub fn generate_certs(domains: &[String]) -> (CertAndKey, Vec<CertAndKey>) {
-    let (ca_cert, ca_cert_and_key) = ca_cert();
+// Load CA from its cert and key so it can sign more certs.
+fn load_ca(ca_cert_and_key: &CertAndKey) -> Result<Certificate> {
+    let keypair = KeyPair::from_pem(&ca_cert_and_key.key).context("invalid CA key")?;
+    let params = CertificateParams::from_ca_cert_pem(&ca_cert_and_key.cert, keypair)
+        .context("invalid CA cert")?;
+    Certificate::from_params(params).context("cannot load CA")
+}
+
+// Generated CA and node certs, kept so that a rerun can reuse them.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct KeyMaterial {
+    pub ca: Option<CertAndKey>,
+    // node address -> node's cert
+    pub certs: BTreeMap<String, CertAndKey>,
+}
+
+impl KeyMaterial {
+    pub fn with_ca(ca: CertAndKey) -> Self {
+        Self {
+            ca: Some(ca),
+            certs: BTreeMap::new(),
+        }
+    }
+
+    // Return the CA, generate one if there isn't.
+    pub fn ca_or_generate(&mut self) -> CertAndKey {
+        self.ca.get_or_insert_with(|| ca_cert().1).clone()
+    }
+
+    // Return certs for domains, generate the missing ones with the CA.
+    pub fn certs_or_generate(&mut self, domains: &[String]) -> Result<Vec<CertAndKey>> {
+        let ca = self.ca_or_generate();
+        let missing: Vec<String> = domains
+            .iter()
+            .filter(|d| !self.certs.contains_key(*d))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            let generated = generate_certs_with_ca(&ca, &missing)?;
+            self.certs.extend(missing.into_iter().zip(generated));
+        }
+        Ok(domains.iter().map(|d| self.certs[d].clone()).collect())
+    }
+}
+
+// Generate certs for domains signed by an existing CA.
+fn generate_certs_with_ca(
+    ca_cert_and_key: &CertAndKey,
+    domains: &[String],
+) -> Result<Vec<CertAndKey>> {
+    let ca_cert = load_ca(ca_cert_and_key)?;
     let peer_cert_and_keys = domains
         .iter()
         .map(|domain| cert(domain, &ca_cert).1)
         .collect();
 
-    (ca_cert_and_key, peer_cert_and_keys)
+    Ok(peer_cert_and_keys)
 }
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::migrate::new;
+
+// The consensus service of the new chain.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsensusKind {
+    #[default]
+    Raft,
+    Bft,
+    Overlord,
+}
+
+impl ConsensusKind {
+    pub const VARIANTS: [&'static str; 3] = ["raft", "bft", "overlord"];
+
+    // The consensus section of a node, everything it needs is in the controller config.
+    // Whatever the target, the old raft state is left behind and the new consensus
+    // starts afresh from the controller's block height.
+    pub fn generate(self, controller: &new::ControllerConfig) -> new::Consensus {
+        match self {
+            Self::Raft => new::Consensus::Raft(new::ConsensusRaftConfig {
+                controller_port: controller.controller_port,
+                network_port: controller.network_port,
+                node_addr: controller.node_address.clone(),
+                grpc_listen_port: controller.consensus_port,
+            }),
+            Self::Bft => new::Consensus::Bft(new::ConsensusBftConfig {
+                consensus_port: controller.consensus_port,
+                controller_port: controller.controller_port,
+                kms_port: controller.kms_port,
+                network_port: controller.network_port,
+                key_id: controller.key_id,
+                node_address: controller.node_address.clone(),
+            }),
+            Self::Overlord => new::Consensus::Overlord(new::ConsensusOverlordConfig {
+                consensus_port: controller.consensus_port,
+                controller_port: controller.controller_port,
+                kms_port: controller.kms_port,
+                network_port: controller.network_port,
+                key_id: controller.key_id,
+                node_address: controller.node_address.clone(),
+            }),
+        }
+    }
+
+    // Overlord validators are BLS public keys rather than node addresses,
+    // and the old chain has none of them.
+    pub fn needs_validator_keys(self) -> bool {
+        self == Self::Overlord
+    }
+}
+
+impl FromStr for ConsensusKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raft" => Ok(Self::Raft),
+            "bft" => Ok(Self::Bft),
+            "overlord" => Ok(Self::Overlord),
+            _ => bail!("unknown consensus `{}`", s),
+        }
+    }
+}
+
+impl fmt::Display for ConsensusKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Raft => "raft",
+            Self::Bft => "bft",
+            Self::Overlord => "overlord",
+        };
+        f.write_str(s)
+    }
+}
+
+impl new::Consensus {
+    pub fn kind(&self) -> ConsensusKind {
+        match self {
+            Self::Raft(_) => ConsensusKind::Raft,
+            Self::Bft(_) => ConsensusKind::Bft,
+            Self::Overlord(_) => ConsensusKind::Overlord,
+        }
+    }
+
+    pub fn controller_port(&self) -> u16 {
+        match self {
+            Self::Raft(c) => c.controller_port,
+            Self::Bft(c) => c.controller_port,
+            Self::Overlord(c) => c.controller_port,
+        }
+    }
+}
+
+// Validator keys by node address, for consensus that doesn't use addresses as validators.
+#[derive(Default)]
+pub struct ValidatorKeys {
+    pub keys: HashMap<String, String>,
+}
+
+impl ValidatorKeys {
+    // The file is a toml table from node address to validator key, e.g.
+    // ```toml
+    // 0x3f91e1969fc0a43d8a3429ce07e3a691533093a5 = "0x8a1c...bls public key"
+    // ```
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let keys = match path {
+            Some(path) => {
+                let s = fs::read_to_string(path).with_context(|| {
+                    format!(
+                        "cannot read validator keys file `{}`",
+                        path.to_string_lossy()
+                    )
+                })?;
+                toml::from_str(&s).context("invalid validator keys file")?
+            }
+            None => HashMap::new(),
+        };
+        Ok(Self { keys })
+    }
+
+    // The keys of `validators`, in the same order, every one of them must have a key.
+    pub fn keys_of(&self, validators: &[String]) -> Result<Vec<String>> {
+        let missing: Vec<&str> = validators
+            .iter()
+            .filter(|v| !self.keys.contains_key(*v))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "no validator key for {}, give them with `--validator-keys`",
+                missing.join(", ")
+            );
+        }
+        Ok(validators.iter().map(|v| self.keys[v].clone()).collect())
+    }
+
+    // `validators` with known keys replaced by their node addresses.
+    pub fn addresses_of(&self, validators: &[String]) -> Vec<String> {
+        validators
+            .iter()
+            .map(|v| {
+                self.keys
+                    .iter()
+                    .find(|(_, k)| *k == v)
+                    .map_or(v, |(addr, _)| addr)
+                    .clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> ValidatorKeys {
+        ValidatorKeys {
+            keys: HashMap::from([
+                ("0xaa".to_string(), "0xbls-a".to_string()),
+                ("0xbb".to_string(), "0xbls-b".to_string()),
+            ]),
+        }
+    }
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn controller() -> new::ControllerConfig {
+        new::ControllerConfig {
+            consensus_port: 50001,
+            controller_port: 50004,
+            executor_port: 50002,
+            storage_port: 50003,
+            kms_port: 50005,
+            network_port: 50000,
+            key_id: 7,
+            node_address: "0xaa".to_string(),
+            package_limit: new::DEFAULT_PACKAGE_LIMIT,
+        }
+    }
+
+    #[test]
+    fn consensus_kind_round_trips() {
+        for s in ConsensusKind::VARIANTS {
+            let kind: ConsensusKind = s.parse().unwrap();
+            assert_eq!(kind.to_string(), s);
+            assert_eq!(kind.generate(&controller()).kind(), kind);
+            assert_eq!(kind.generate(&controller()).controller_port(), 50004);
+        }
+        assert!("pbft".parse::<ConsensusKind>().is_err());
+    }
+
+    #[test]
+    fn only_overlord_needs_validator_keys() {
+        assert!(!ConsensusKind::Raft.needs_validator_keys());
+        assert!(!ConsensusKind::Bft.needs_validator_keys());
+        assert!(ConsensusKind::Overlord.needs_validator_keys());
+    }
+
+    #[test]
+    fn keys_of_validators_in_order() {
+        assert_eq!(
+            keys().keys_of(&strings(&["0xbb", "0xaa"])).unwrap(),
+            strings(&["0xbls-b", "0xbls-a"])
+        );
+        assert!(keys().keys_of(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keys_of_fails_listing_validators_without_key() {
+        let e = keys()
+            .keys_of(&strings(&["0xaa", "0xcc", "0xdd"]))
+            .unwrap_err();
+        assert!(e.to_string().starts_with("no validator key for 0xcc, 0xdd"));
+    }
+
+    #[test]
+    fn addresses_of_maps_known_keys_back() {
+        assert_eq!(
+            keys().addresses_of(&strings(&["0xbls-b", "0xaa", "0xbls-x"])),
+            strings(&["0xbb", "0xaa", "0xbls-x"])
+        );
+        assert!(ValidatorKeys::default()
+            .addresses_of(&strings(&["0xaa"]))
+            .eq(&strings(&["0xaa"])));
+    }
+}
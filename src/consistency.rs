@@ -0,0 +1,268 @@
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::migrate::old;
+use crate::migrate::NodeConfigMigrate;
+
+impl old::InitSysConfig {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("version", self.version.to_string()),
+            ("admin", self.admin.clone()),
+            ("block_interval", self.block_interval.to_string()),
+            ("chain_id", self.chain_id.clone()),
+            ("validators", format!("{:?}", self.validators)),
+        ]
+    }
+}
+
+impl old::Genesis {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("timestamp", self.timestamp.to_string()),
+            ("prevhash", self.prevhash.clone()),
+        ]
+    }
+}
+
+// Field-level diff of `init_sys_config.toml` and `genesis.toml` across nodes.
+// Return an empty string if all nodes agree.
+pub fn diff_nodes(node_dirs: &[PathBuf], nodes: &[NodeConfigMigrate]) -> String {
+    let node_names: Vec<String> = node_dirs
+        .iter()
+        .map(|d| {
+            d.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| d.to_string_lossy().into_owned())
+        })
+        .collect();
+
+    let mut diff = String::new();
+    let sys_configs: Vec<_> = nodes.iter().map(|n| n.system_config.fields()).collect();
+    diff_file(&mut diff, "init_sys_config.toml", &node_names, &sys_configs);
+    let genesis_blocks: Vec<_> = nodes.iter().map(|n| n.genesis_block.fields()).collect();
+    diff_file(&mut diff, "genesis.toml", &node_names, &genesis_blocks);
+    diff
+}
+
+fn diff_file(
+    diff: &mut String,
+    file_name: &str,
+    node_names: &[String],
+    fields_per_node: &[Vec<(&'static str, String)>],
+) {
+    let first = match fields_per_node.first() {
+        Some(first) => first,
+        None => return,
+    };
+    for (i, (field, value)) in first.iter().enumerate() {
+        if fields_per_node.iter().all(|fields| &fields[i].1 == value) {
+            continue;
+        }
+        diff.push_str(&format!("`{}` `{}`:\n", file_name, field));
+        for (name, fields) in node_names.iter().zip(fields_per_node) {
+            diff.push_str(&format!("    `{}`: {}\n", name, fields[i].1));
+        }
+    }
+}
+
+// Check all nodes agree on system config and genesis, and return the index
+// of the node whose config will be used for the new chain.
+pub fn pick_authoritative_node(
+    node_dirs: &[PathBuf],
+    nodes: &[NodeConfigMigrate],
+    authoritative_node: Option<&str>,
+) -> Result<usize> {
+    let diff = diff_nodes(node_dirs, nodes);
+    match authoritative_node {
+        Some(name) => {
+            let index = node_dirs
+                .iter()
+                .position(|d| d.file_name() == Some(OsStr::new(name)))
+                .with_context(|| format!("authoritative node `{}` not found", name))?;
+            if !diff.is_empty() {
+                eprintln!(
+                    "warning: nodes disagree on system config or genesis, \
+                    using the one from `{}`:\n{}",
+                    name, diff
+                );
+            }
+            Ok(index)
+        }
+        None if diff.is_empty() => Ok(0),
+        None => bail!(
+            "nodes disagree on system config or genesis, the new chain would fork. \
+            Fix the old configs or choose one with `--authoritative-node`:\n{}",
+            diff
+        ),
+    }
+}
+
+// What consensus membership will look like after migration.
+#[derive(Serialize, Clone, Default)]
+pub struct Membership {
+    pub validators: Vec<String>,
+    // Validators without a node dir, e.g. remote or lost nodes.
+    pub missing_validators: Vec<String>,
+    // Node dirs whose address is not a validator.
+    pub observers: Vec<String>,
+}
+
+impl Membership {
+    pub fn new(validators: &[String], node_addrs: &[String]) -> Self {
+        let normalize = |addr: &str| addr.trim_start_matches("0x").to_lowercase();
+        let node_set: Vec<String> = node_addrs.iter().map(|a| normalize(a)).collect();
+        let validator_set: Vec<String> = validators.iter().map(|v| normalize(v)).collect();
+
+        let missing_validators = validators
+            .iter()
+            .filter(|v| !node_set.contains(&normalize(v)))
+            .cloned()
+            .collect();
+        let observers = node_addrs
+            .iter()
+            .filter(|a| !validator_set.contains(&normalize(a)))
+            .cloned()
+            .collect();
+
+        Self {
+            validators: validators.to_vec(),
+            missing_validators,
+            observers,
+        }
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        for v in &self.missing_validators {
+            warnings.push(format!(
+                "validator `{}` has no node dir, it must be a remote or lost node",
+                v
+            ));
+        }
+        for a in &self.observers {
+            warnings.push(format!(
+                "node `{}` is not a validator, it will be an observer",
+                a
+            ));
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A node with the given admin and genesis timestamp, the rest is the same for all.
+    fn node(admin: &str, timestamp: u64) -> NodeConfigMigrate {
+        NodeConfigMigrate {
+            controller_port: 50004,
+            consensus_port: 50001,
+            executor_port: 50002,
+            network_port: 50000,
+            kms_port: 50005,
+            storage_port: 50003,
+            node_addr: "0x00".to_string(),
+            genesis_block: old::Genesis {
+                timestamp,
+                prevhash: "0x00".to_string(),
+            },
+            system_config: old::InitSysConfig {
+                version: 0,
+                admin: admin.to_string(),
+                block_interval: 3,
+                chain_id: "0x63586a3c".to_string(),
+                validators: vec!["0xaa".to_string(), "0xbb".to_string()],
+            },
+            kms_password: String::new(),
+            key_id: 1,
+            network_config: old::NetworkConfig {
+                port: 40000,
+                peers: vec![],
+            },
+        }
+    }
+
+    fn dirs(n: usize) -> Vec<PathBuf> {
+        (0..n)
+            .map(|i| PathBuf::from(format!("old/test-chain-{}", i)))
+            .collect()
+    }
+
+    #[test]
+    fn no_diff_if_nodes_agree() {
+        let nodes = [node("0xad", 1), node("0xad", 1), node("0xad", 1)];
+        assert_eq!(diff_nodes(&dirs(3), &nodes), "");
+        assert_eq!(diff_nodes(&[], &[]), "");
+        assert_eq!(pick_authoritative_node(&dirs(3), &nodes, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn diff_lists_every_node_for_every_differing_field() {
+        let nodes = [node("0xad", 1), node("0xad", 2), node("0xae", 1)];
+        assert_eq!(
+            diff_nodes(&dirs(3), &nodes),
+            "`init_sys_config.toml` `admin`:\n\
+            \x20   `test-chain-0`: 0xad\n\
+            \x20   `test-chain-1`: 0xad\n\
+            \x20   `test-chain-2`: 0xae\n\
+            `genesis.toml` `timestamp`:\n\
+            \x20   `test-chain-0`: 1\n\
+            \x20   `test-chain-1`: 2\n\
+            \x20   `test-chain-2`: 1\n"
+        );
+    }
+
+    #[test]
+    fn disagreeing_nodes_need_an_authoritative_one() {
+        let nodes = [node("0xad", 1), node("0xae", 1)];
+        let e = pick_authoritative_node(&dirs(2), &nodes, None).unwrap_err();
+        assert!(e.to_string().starts_with("nodes disagree"));
+        assert_eq!(
+            pick_authoritative_node(&dirs(2), &nodes, Some("test-chain-1")).unwrap(),
+            1
+        );
+        let e = pick_authoritative_node(&dirs(2), &nodes, Some("test-chain-2")).unwrap_err();
+        assert_eq!(e.to_string(), "authoritative node `test-chain-2` not found");
+    }
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn membership_of_a_full_chain() {
+        let m = Membership::new(&strings(&["0xaa", "0xbb"]), &strings(&["0xbb", "0xaa"]));
+        assert_eq!(m.validators, strings(&["0xaa", "0xbb"]));
+        assert!(m.missing_validators.is_empty());
+        assert!(m.observers.is_empty());
+        assert!(m.warnings().is_empty());
+    }
+
+    #[test]
+    fn membership_ignores_prefix_and_case() {
+        let m = Membership::new(&strings(&["0xAbCd", "ef01"]), &strings(&["abcd", "0xEF01"]));
+        assert!(m.missing_validators.is_empty());
+        assert!(m.observers.is_empty());
+    }
+
+    #[test]
+    fn membership_finds_missing_validators_and_observers() {
+        let m = Membership::new(
+            &strings(&["0xaa", "0xbb", "0xcc"]),
+            &strings(&["0xaa", "0xdd"]),
+        );
+        assert_eq!(m.missing_validators, strings(&["0xbb", "0xcc"]));
+        assert_eq!(m.observers, strings(&["0xdd"]));
+        assert_eq!(m.warnings().len(), 3);
+
+        let m = Membership::new(&[], &strings(&["0xaa"]));
+        assert_eq!(m.observers, strings(&["0xaa"]));
+    }
+}
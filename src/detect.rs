@@ -0,0 +1,215 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::pipeline::Version;
+
+// Per service config files only a 6.1.0 node dir has.
+const V6_1_0_FILES: [&str; 5] = [
+    "controller-config.toml",
+    "consensus-config.toml",
+    "network-config.toml",
+    "init_sys_config.toml",
+    "genesis.toml",
+];
+
+// A version told from the layout, with what gave it away.
+pub struct Detected {
+    pub version: Version,
+    pub evidence: String,
+}
+
+impl fmt::Display for Detected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, from {}", self.version, self.evidence)
+    }
+}
+
+// Tell the version of a node dir by its config files.
+// 6.1.0 has a config file per service, 6.3.0 a single `config.toml` with a section per service.
+// 6.1.0 node dirs may have a `config.toml` too, but not with those sections.
+pub fn detect_node(node_dir: &Path) -> Result<Detected> {
+    let old_files: Vec<String> = V6_1_0_FILES
+        .iter()
+        .filter(|f| node_dir.join(f).is_file())
+        .map(|f| format!("`{}`", f))
+        .collect();
+    let sections = config_sections(node_dir);
+    let new_sections: Vec<String> = sections
+        .iter()
+        .filter(|s| *s == "controller" || s.starts_with("network_") || s.starts_with("consensus_"))
+        .map(|s| format!("`[{}]`", s))
+        .collect();
+
+    match (old_files.is_empty(), new_sections.is_empty()) {
+        (false, true) => Ok(Detected {
+            version: Version::V6_1_0,
+            evidence: old_files.join(", "),
+        }),
+        (true, false) => Ok(Detected {
+            version: Version::V6_3_0,
+            evidence: format!("`config.toml` with {}", new_sections.join(", ")),
+        }),
+        (false, false) => bail!(
+            "`{}` looks like both 6.1.0, with {}, and 6.3.0, with `config.toml` with {}",
+            node_dir.to_string_lossy(),
+            old_files.join(", "),
+            new_sections.join(", ")
+        ),
+        (true, true) => bail!(
+            "cannot tell the version of `{}`, it has neither per service config files \
+            like `controller-config.toml` nor a `config.toml` with `[controller]`",
+            node_dir.to_string_lossy()
+        ),
+    }
+}
+
+// Tell the version of the whole chain, every node dir must agree on it.
+// Node dirs are named `<chain>-<node id>` in 6.1.0 and `<chain>-<node address>` in 6.3.0,
+// so every `<chain>-*` dir named like either is looked at unless they're given.
+// Other `<chain>-*` dirs, e.g. backups, are left to `load_node_dirs` to skip with a warning.
+pub fn detect_chain(
+    chain_data_dir: &Path,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+) -> Result<Detected> {
+    let node_dirs = if explicit_node_dirs.is_empty() {
+        candidate_node_dirs(chain_data_dir, chain_name)?
+    } else {
+        explicit_node_dirs.to_vec()
+    };
+    if node_dirs.is_empty() {
+        bail!(
+            "no node dir named `{}-<node id>` or `{}-<node address>` found in `{}`",
+            chain_name,
+            chain_name,
+            chain_data_dir.to_string_lossy()
+        );
+    }
+
+    let detected = node_dirs
+        .iter()
+        .map(|d| detect_node(d))
+        .collect::<Result<Vec<_>>>()?;
+    let first = &detected[0];
+    if detected.iter().any(|d| d.version != first.version) {
+        let versions: Vec<String> = node_dirs
+            .iter()
+            .zip(&detected)
+            .map(|(d, v)| format!("  `{}`: {}", d.to_string_lossy(), v))
+            .collect();
+        bail!(
+            "node dirs are of different versions:\n{}",
+            versions.join("\n")
+        );
+    }
+    Ok(Detected {
+        version: first.version,
+        evidence: first.evidence.clone(),
+    })
+}
+
+// Refuse anything but a 6.1.0 chain, the only one whose node configs this tool reads.
+pub fn ensure_v6_1_0(
+    chain_data_dir: &Path,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+) -> Result<()> {
+    let detected = detect_chain(chain_data_dir, chain_name, explicit_node_dirs)?;
+    if detected.version != Version::V6_1_0 {
+        bail!(
+            "`{}` is at {}, not 6.1.0, detected from {}",
+            chain_data_dir.to_string_lossy(),
+            detected.version,
+            detected.evidence
+        );
+    }
+    Ok(())
+}
+
+fn candidate_node_dirs(chain_data_dir: &Path, chain_name: &str) -> Result<Vec<PathBuf>> {
+    let prefix = format!("{}-", chain_name);
+    let entries = fs::read_dir(chain_data_dir).with_context(|| {
+        format!(
+            "cannot read chain data folder `{}`",
+            chain_data_dir.to_string_lossy()
+        )
+    })?;
+    let mut dirs = vec![];
+    for ent in entries.flatten() {
+        let is_candidate = ent
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .is_some_and(is_node_suffix);
+        if is_candidate && ent.path().is_dir() {
+            dirs.push(ent.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+// A node id like `load_node_dirs` expects, or a node address without `0x` like `new_node_dir` makes.
+fn is_node_suffix(suffix: &str) -> bool {
+    let is_id = suffix.parse::<u64>().is_ok();
+    let is_addr = suffix.len() == 40 && suffix.bytes().all(|b| b.is_ascii_hexdigit());
+    is_id || is_addr
+}
+
+// Top level tables of `config.toml`, empty if there's none or it's not toml.
+fn config_sections(node_dir: &Path) -> Vec<String> {
+    let content = match fs::read_to_string(node_dir.join("config.toml")) {
+        Ok(content) => content,
+        Err(_) => return vec![],
+    };
+    match content.parse::<toml::Value>() {
+        Ok(toml::Value::Table(table)) => table
+            .into_iter()
+            .filter(|(_, v)| v.is_table())
+            .map(|(k, _)| k)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_ids_are_node_suffixes() {
+        assert!(is_node_suffix("0"));
+        assert!(is_node_suffix("12"));
+        assert!(is_node_suffix("007"));
+        assert!(!is_node_suffix("-1"));
+        assert!(!is_node_suffix("18446744073709551616"));
+    }
+
+    #[test]
+    fn node_addresses_are_node_suffixes() {
+        assert!(is_node_suffix("3f91e1969fc0a43d8a3429ce07e3a691533093a5"));
+        assert!(is_node_suffix("3F91E1969FC0A43D8A3429CE07E3A691533093A5"));
+        assert!(is_node_suffix("3f91E1969fc0A43d8a3429ce07e3a691533093A5"));
+        // `new_node_dir` strips the `0x`.
+        assert!(!is_node_suffix(
+            "0x3f91e1969fc0a43d8a3429ce07e3a691533093a5"
+        ));
+        assert!(!is_node_suffix("3f91e1969fc0a43d8a3429ce07e3a691533093a"));
+        assert!(!is_node_suffix("3f91e1969fc0a43d8a3429ce07e3a691533093a5a"));
+        assert!(!is_node_suffix("3f91e1969fc0a43d8a3429ce07e3a691533093g5"));
+    }
+
+    #[test]
+    fn other_names_are_not_node_suffixes() {
+        assert!(!is_node_suffix(""));
+        assert!(!is_node_suffix("backup"));
+        assert!(!is_node_suffix("0.old"));
+        assert!(!is_node_suffix("0 "));
+    }
+}
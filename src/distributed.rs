@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::cert::CertAndKey;
+use crate::cert::KeyMaterial;
+use crate::consensus::ConsensusKind;
+use crate::detect::detect_node;
+use crate::journal::Journal;
+use crate::journal::OutputOpts;
+use crate::migrate::execute_meta;
+use crate::migrate::execute_node;
+use crate::migrate::fill_network_tls_certs;
+use crate::migrate::migrate_configs;
+use crate::migrate::new;
+use crate::migrate::plan_meta;
+use crate::migrate::plan_node;
+use crate::migrate::write_chain_manifest;
+use crate::migrate::ChainConfigs;
+use crate::migrate::MigrateOpts;
+use crate::migrate::NodeConfigMigrate;
+use crate::pipeline::Version;
+use crate::plan::ensure_enough_space;
+use crate::plan::NodePlan;
+use crate::progress::Progress;
+use crate::progress::ProgressFormat;
+use crate::running;
+use crate::staging;
+use crate::staging::OnConflict;
+use crate::transfer::free_space;
+use crate::transfer::Transfer;
+
+// Chain-wide info shared by all nodes, for migrating nodes on separate machines.
+#[derive(Serialize, Deserialize)]
+pub struct ChainManifest {
+    pub chain_name: String,
+    #[serde(default)]
+    pub consensus: ConsensusKind,
+    // The first node, whose machine writes the new metadata dir with the node's log4rs yamls
+    // and kms db, as `migrate` does. Manifests of older versions have none.
+    #[serde(default)]
+    pub meta_node: Option<String>,
+    // The new chain's meta config, which contains the network service, the CA and every peer's domain.
+    pub meta_config: new::MetaConfig,
+}
+
+impl ChainManifest {
+    fn load(path: &Path) -> Result<Self> {
+        let s = fs::read_to_string(path)
+            .with_context(|| format!("cannot read manifest `{}`", path.to_string_lossy()))?;
+        toml::from_str(&s).context("invalid manifest")
+    }
+
+    // None if the new chain doesn't use `network_tls`.
+    fn ca_cert_and_key(&self) -> Option<CertAndKey> {
+        let current_config = &self.meta_config.current_config;
+        match (&current_config.ca_cert_pem, &current_config.ca_key_pem) {
+            (Some(cert), Some(key)) => Some(CertAndKey {
+                cert: cert.clone(),
+                key: key.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Produce the chain-wide manifest. Only node configs are needed, not their data.
+pub fn prepare<P, Q>(
+    chain_data_dir: P,
+    manifest_path: Q,
+    chain_name: &str,
+    opts: &MigrateOpts,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let manifest_path = manifest_path.as_ref();
+    let ChainConfigs {
+        node_configs,
+        meta_config,
+        skipped_dirs,
+        membership,
+        ..
+    } = migrate_configs(
+        chain_data_dir.as_ref(),
+        chain_name,
+        opts,
+        &mut KeyMaterial::default(),
+    )?;
+    for (d, reason) in skipped_dirs {
+        eprintln!("warning: skip dir `{}`: {}", d.to_string_lossy(), reason);
+    }
+    for w in membership.warnings() {
+        eprintln!("warning: {}", w);
+    }
+
+    let manifest = ChainManifest {
+        chain_name: chain_name.to_string(),
+        consensus: opts.consensus,
+        meta_node: node_configs
+            .first()
+            .map(|c| c.controller.node_address.clone()),
+        meta_config,
+    };
+    let mut manifest_toml = File::create(manifest_path).with_context(|| {
+        format!(
+            "cannot create manifest `{}`",
+            manifest_path.to_string_lossy()
+        )
+    })?;
+    let manifest_content =
+        toml::to_string_pretty(&manifest).context("cannot serialize manifest")?;
+    manifest_toml
+        .write_all(manifest_content.as_bytes())
+        .context("cannot write manifest")?;
+
+    Ok(())
+}
+
+// Plan migrating a single old node dir with the chain-wide manifest.
+fn plan_single_node(
+    manifest: &ChainManifest,
+    old_node_dir: &Path,
+    new_chain_data_dir: &Path,
+    keys: &mut KeyMaterial,
+    transfer: Transfer,
+) -> Result<NodePlan> {
+    let detected = detect_node(old_node_dir)?;
+    ensure!(
+        detected.version == Version::V6_1_0,
+        "`{}` is at {}, not 6.1.0, detected from {}",
+        old_node_dir.to_string_lossy(),
+        detected.version,
+        detected.evidence
+    );
+    let old = NodeConfigMigrate::extract_from(old_node_dir).with_context(|| {
+        format!(
+            "cannot extract info from old node config in `{}`",
+            old_node_dir.to_string_lossy()
+        )
+    })?;
+    let mut node_config = old.generate_new(manifest.consensus, manifest.meta_config.network.kind());
+    // Validator keys are only in the manifest, the old node knows validators by address.
+    if manifest.consensus.needs_validator_keys() {
+        node_config.system_config.validators =
+            manifest.meta_config.system_config.validators.clone();
+    }
+
+    // The manifest is authoritative.
+    if node_config.system_config != manifest.meta_config.system_config
+        || node_config.genesis_block != manifest.meta_config.genesis_block
+    {
+        eprintln!(
+            "warning: node `{}` disagrees with the manifest on system config or genesis, \
+            using the one from the manifest",
+            old_node_dir.to_string_lossy()
+        );
+        node_config.system_config = manifest.meta_config.system_config.clone();
+        node_config.genesis_block = manifest.meta_config.genesis_block.clone();
+    }
+
+    // Only `network_tls` needs the node's own address, for its cert and peers' domains.
+    if let new::MetaNetwork::Tls(network) = &manifest.meta_config.network {
+        let peers = &network.peers;
+        let itself = peers
+            .iter()
+            .find(|p| p.domain.as_deref() == Some(node_config.controller.node_address.as_str()))
+            .with_context(|| {
+                format!(
+                    "node `{}` not found in the manifest",
+                    node_config.controller.node_address
+                )
+            })?;
+        node_config.network_host.replace(itself.host.clone());
+        node_config.network_port.replace(itself.port);
+
+        let host_port_to_addr: HashMap<(String, u16), String> = peers
+            .iter()
+            .filter_map(|p| {
+                p.domain
+                    .clone()
+                    .map(|domain| ((p.host.clone(), p.port), domain))
+            })
+            .collect();
+        let mut node_configs = [node_config];
+        fill_network_tls_certs(&mut node_configs, keys, &host_port_to_addr)
+            .context("cannot fill network_tls info for node config")?;
+        [node_config] = node_configs;
+    }
+
+    plan_node(
+        old_node_dir.to_path_buf(),
+        node_config,
+        new_chain_data_dir,
+        &manifest.chain_name,
+        transfer,
+    )
+}
+
+pub fn migrate_single_node<P, Q, R>(
+    manifest_path: P,
+    old_node_dir: Q,
+    new_chain_data_dir: R,
+    transfer: Transfer,
+    progress: Option<ProgressFormat>,
+    on_conflict: OnConflict,
+    force: bool,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: AsRef<Path>,
+{
+    let manifest = ChainManifest::load(manifest_path.as_ref())?;
+    let old_node_dir = old_node_dir.as_ref();
+    let new_chain_data_dir = new_chain_data_dir.as_ref();
+
+    let staging_dir = staging::staging_dir(new_chain_data_dir)?;
+
+    staging::ensure_presync_picked_up(&staging_dir, transfer.mode)?;
+    let chain_data_dir = old_node_dir.parent().unwrap_or_else(|| Path::new(""));
+    // The rest of the options are fixed by the manifest.
+    let output_opts = OutputOpts {
+        consensus: manifest.consensus,
+        network: manifest.meta_config.network.kind(),
+        data_mode: transfer.mode,
+        ..Default::default()
+    };
+    let mut journal = Journal::load_or_new(
+        &staging_dir,
+        chain_data_dir,
+        &manifest.chain_name,
+        output_opts,
+    )?;
+    match (manifest.ca_cert_and_key(), &journal.keys.ca) {
+        (Some(ca), Some(journal_ca)) => ensure!(
+            journal_ca.cert == ca.cert,
+            "the journal in `{}` was made with another manifest",
+            staging_dir.to_string_lossy()
+        ),
+        (Some(ca), None) => journal.keys = KeyMaterial::with_ca(ca),
+        (None, _) => (),
+    }
+
+    let node = plan_single_node(
+        &manifest,
+        old_node_dir,
+        &staging_dir,
+        &mut journal.keys,
+        transfer,
+    )?;
+    let mut meta = if manifest.meta_node.as_ref() == Some(&node.config.controller.node_address) {
+        Some(plan_meta(
+            old_node_dir,
+            &staging_dir,
+            &manifest.chain_name,
+            manifest.meta_config,
+        )?)
+    } else {
+        None
+    };
+
+    let mut node = Some(node);
+    let dirs = node
+        .iter()
+        .map(|n| &n.new_dir)
+        .chain(meta.iter().map(|m| &m.new_dir));
+    let conflicts = staging::conflicts(new_chain_data_dir, dirs.map(PathBuf::as_path));
+    match on_conflict {
+        OnConflict::Fail => staging::ensure_no_conflicts(&conflicts)?,
+        OnConflict::Overwrite => {
+            for existing in &conflicts {
+                eprintln!(
+                    "warning: `{}` already exists, will be overwritten",
+                    existing.to_string_lossy()
+                );
+            }
+        }
+        OnConflict::Skip => {
+            for existing in &conflicts {
+                eprintln!("`{}` already exists, skip", existing.to_string_lossy());
+            }
+            let exists = |dir: &Path| conflicts.iter().any(|d| d.file_name() == dir.file_name());
+            node = node.filter(|n| !exists(&n.new_dir));
+            meta = meta.filter(|m| !exists(&m.new_dir));
+        }
+    }
+    if node.is_none() && meta.is_none() {
+        return Ok(());
+    }
+
+    if let Some(node) = &node {
+        running::ensure_stopped([(node.old_dir.as_path(), &node.config)], force)?;
+    }
+    let space_needed = node
+        .iter()
+        .flat_map(|n| &n.copies)
+        .chain(meta.iter().flat_map(|m| &m.copies))
+        .map(|op| op.space)
+        .sum();
+    ensure_enough_space(space_needed, free_space(&staging_dir)?, new_chain_data_dir)?;
+    journal.save()?;
+
+    let nodes: Vec<NodePlan> = node.into_iter().collect();
+    let progress = Progress::new(&nodes, &staging_dir, new_chain_data_dir, progress);
+    let mut run = || -> Result<()> {
+        if let Some(meta) = &meta {
+            execute_meta(meta, &mut journal)?;
+        }
+        if let Some(node) = nodes.first() {
+            let journal = Mutex::new(&mut journal);
+            progress.report_while(|| execute_node(node, &journal, progress.node(0)))?;
+        }
+        // Other nodes are migrated elsewhere, only this one's manifest is recorded.
+        if let Some(meta) = &meta {
+            write_chain_manifest(
+                &manifest.chain_name,
+                &staging_dir,
+                meta,
+                &nodes,
+                &mut journal,
+            )?;
+        }
+        Ok(())
+    };
+    if let Err(e) = run() {
+        staging::discard(
+            &staging_dir,
+            staging::keep_reason(&staging_dir, transfer.mode),
+        );
+        return Err(e);
+    }
+    staging::commit(&staging_dir, new_chain_data_dir, on_conflict)
+}
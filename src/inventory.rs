@@ -0,0 +1,94 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::migrate::NodeConfigMigrate;
+use crate::migrate::CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS;
+use crate::migrate::DISCARDED_FILES_AND_DIRS;
+use crate::migrate::LOG4RS_AND_KMS_DB_FILES;
+
+// What becomes of an entry in an old node dir.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Fate {
+    // Copied into the new node dir as is.
+    Migrated,
+    // Read into the new `config.toml`.
+    Transformed,
+    // Not needed by the new chain.
+    Discarded,
+    // Not known to this tool, lost unless copied by hand.
+    Unknown,
+}
+
+impl Fate {
+    pub const ALL: [Fate; 4] = [
+        Fate::Migrated,
+        Fate::Transformed,
+        Fate::Discarded,
+        Fate::Unknown,
+    ];
+
+    pub fn of(name: &str) -> Self {
+        if LOG4RS_AND_KMS_DB_FILES.contains(&name)
+            || CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS.contains(&name)
+        {
+            Self::Migrated
+        } else if NodeConfigMigrate::CONFIG_FILES.contains(&name) {
+            Self::Transformed
+        } else if DISCARDED_FILES_AND_DIRS.contains(&name) || name.ends_with(".pid") {
+            Self::Discarded
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+impl fmt::Display for Fate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Migrated => "migrated",
+            Self::Transformed => "transformed",
+            Self::Discarded => "discarded",
+            Self::Unknown => "unknown",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub fate: Fate,
+}
+
+// Every entry in the old node dir, sorted by name.
+pub fn inventory(old_node_dir: &Path) -> Result<Vec<Entry>> {
+    let read_err = || format!("cannot read dir `{}`", old_node_dir.to_string_lossy());
+    let mut entries = fs::read_dir(old_node_dir)
+        .with_context(read_err)?
+        .map(|ent| {
+            let name = ent.with_context(read_err)?.file_name();
+            let name = name.to_string_lossy().into_owned();
+            Ok(Entry {
+                fate: Fate::of(&name),
+                name,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+// The names of `entries` with `fate`, quoted for messages.
+pub fn names(entries: &[Entry], fate: Fate) -> Vec<String> {
+    entries
+        .iter()
+        .filter(|e| e.fate == fate)
+        .map(|e| format!("`{}`", e.name))
+        .collect()
+}
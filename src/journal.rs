@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::cert::KeyMaterial;
+use crate::consensus::ConsensusKind;
+use crate::network::NetworkKind;
+use crate::transfer::DataMode;
+
+pub const JOURNAL_FILE: &str = ".migration-journal.toml";
+
+// Records the progress of a migration in the output dir, so a rerun can resume
+// where it stopped and reuse the same key material.
+#[derive(Serialize, Deserialize)]
+pub struct Journal {
+    pub chain_name: String,
+    pub chain_dir: PathBuf,
+
+    // Steps that have been started, an interrupted step is started but not completed.
+    pub started: BTreeSet<String>,
+    pub completed: BTreeSet<String>,
+
+    pub keys: KeyMaterial,
+
+    pub opts: OutputOpts,
+
+    // How long each step took in this run.
+    #[serde(skip)]
+    pub timings: Vec<StepTiming>,
+
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+// The options shaping the output, a migration is only resumed with the same ones.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct OutputOpts {
+    pub consensus: ConsensusKind,
+    pub network: NetworkKind,
+    pub data_mode: DataMode,
+    pub authoritative_node: Option<String>,
+    // Self address hints, the shared host and node index -> `host:port`.
+    pub host: Option<String>,
+    pub self_addrs: BTreeMap<String, String>,
+    // Node address -> validator key.
+    pub validator_keys: BTreeMap<String, String>,
+}
+
+impl OutputOpts {
+    // `name: old -> new` for every option that differs.
+    fn diff(&self, other: &Self) -> Vec<String> {
+        let (old, new) = match (toml::Value::try_from(self), toml::Value::try_from(other)) {
+            (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(new))) => (old, new),
+            _ => return vec![],
+        };
+        let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        names
+            .into_iter()
+            .filter(|name| old.get(*name) != new.get(*name))
+            .map(|name| {
+                let show =
+                    |v: Option<&toml::Value>| v.map_or("none".to_string(), |v| v.to_string());
+                format!(
+                    "{}: {} -> {}",
+                    name,
+                    show(old.get(name)),
+                    show(new.get(name))
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct StepTiming {
+    pub step: String,
+    pub seconds: f64,
+    // Completed by a previous run.
+    pub skipped: bool,
+}
+
+impl Journal {
+    // Load the journal in `out_dir` if any.
+    pub fn load(out_dir: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = out_dir.as_ref().join(JOURNAL_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let s = fs::read_to_string(&path)
+            .with_context(|| format!("cannot read journal `{}`", path.to_string_lossy()))?;
+        let mut journal: Self = toml::from_str(&s)
+            .with_context(|| format!("invalid journal `{}`", path.to_string_lossy()))?;
+        journal.path = path;
+        Ok(Some(journal))
+    }
+
+    // Load the journal in `out_dir`, or start a new one if there isn't.
+    pub fn load_or_new(
+        out_dir: impl AsRef<Path>,
+        chain_dir: impl AsRef<Path>,
+        chain_name: &str,
+        opts: OutputOpts,
+    ) -> Result<Self> {
+        let out_dir = out_dir.as_ref();
+        let chain_dir = chain_dir.as_ref();
+        match Self::load(out_dir)? {
+            Some(journal) => {
+                ensure!(
+                    journal.chain_name == chain_name && journal.chain_dir == chain_dir,
+                    "out dir `{}` has a journal for migrating chain `{}` in `{}`, \
+                    use another out dir or remove it",
+                    out_dir.to_string_lossy(),
+                    journal.chain_name,
+                    journal.chain_dir.to_string_lossy()
+                );
+                let diff = journal.opts.diff(&opts);
+                ensure!(
+                    diff.is_empty(),
+                    "out dir `{}` has a journal for migrating with other options, \
+                    rerun with the same options or remove it to start over:\n  {}",
+                    out_dir.to_string_lossy(),
+                    diff.join("\n  ")
+                );
+                eprintln!(
+                    "resume the migration from journal `{}`, {} step(s) completed",
+                    journal.path.to_string_lossy(),
+                    journal.completed.len()
+                );
+                Ok(journal)
+            }
+            None => Ok(Self {
+                chain_name: chain_name.to_string(),
+                chain_dir: chain_dir.to_path_buf(),
+                started: BTreeSet::new(),
+                completed: BTreeSet::new(),
+                keys: KeyMaterial::default(),
+                opts,
+                timings: vec![],
+                path: out_dir.join(JOURNAL_FILE),
+            }),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("cannot create dir `{}`", dir.to_string_lossy()))?;
+        }
+        // Write to a temp file then rename, so the journal is never half written.
+        let content = toml::to_string_pretty(self).context("cannot serialize journal")?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content)
+            .with_context(|| format!("cannot write journal `{}`", tmp.to_string_lossy()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("cannot write journal `{}`", self.path.to_string_lossy()))
+    }
+
+    // Run the step unless it has been completed.
+    // `f` is told whether the step was interrupted last time, so it can clean up first.
+    pub fn step<F>(&mut self, name: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(bool) -> Result<()>,
+    {
+        let start = Instant::now();
+        match self.begin(name)? {
+            Some(interrupted) => {
+                f(interrupted)?;
+                self.complete(name)?;
+                self.record(name, start, false);
+            }
+            None => self.record(name, start, true),
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, name: &str, start: Instant, skipped: bool) {
+        self.timings.push(StepTiming {
+            step: name.to_string(),
+            seconds: start.elapsed().as_secs_f64(),
+            skipped,
+        });
+    }
+
+    // Run the step again next time, it's treated as interrupted.
+    pub fn redo(&mut self, name: &str) -> Result<()> {
+        self.completed.remove(name);
+        self.started.insert(name.to_string());
+        self.save()
+    }
+
+    // Mark the step as started, `None` if it has been completed.
+    fn begin(&mut self, name: &str) -> Result<Option<bool>> {
+        if self.completed.contains(name) {
+            return Ok(None);
+        }
+        let interrupted = !self.started.insert(name.to_string());
+        self.save()?;
+        Ok(Some(interrupted))
+    }
+
+    fn complete(&mut self, name: &str) -> Result<()> {
+        self.completed.insert(name.to_string());
+        self.save()
+    }
+}
+
+// A journal shared by workers migrating nodes concurrently.
+pub type SharedJournal<'a> = Mutex<&'a mut Journal>;
+
+// Like `Journal::step`, but `f` runs without holding the journal.
+pub fn shared_step<F>(journal: &SharedJournal, name: &str, f: F) -> Result<()>
+where
+    F: FnOnce(bool) -> Result<()>,
+{
+    let start = Instant::now();
+    let begun = journal.lock().unwrap().begin(name)?;
+    match begun {
+        Some(interrupted) => {
+            f(interrupted)?;
+            let mut journal = journal.lock().unwrap();
+            journal.complete(name)?;
+            journal.record(name, start, false);
+        }
+        None => journal.lock().unwrap().record(name, start, true),
+    }
+    Ok(())
+}
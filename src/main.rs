@@ -1,10 +1,46 @@
+mod backup;
 mod cert;
+mod consensus;
+mod consistency;
+mod detect;
+mod distributed;
+mod inventory;
+mod journal;
+mod manifest;
 mod migrate;
+mod network;
+mod pipeline;
+mod plan;
+mod presync;
+mod progress;
+mod report;
+mod rollback;
+mod running;
+mod self_addr;
+mod staging;
+mod transfer;
+mod v6_1_0_to_v6_3_0;
+mod validate;
+mod verify;
 
+use std::path::Path;
 use std::path::PathBuf;
 
+use consensus::ConsensusKind;
+use consensus::ValidatorKeys;
+use migrate::MigrateOpts;
+use network::NetworkKind;
+use progress::ProgressFormat;
+use rollback::RollbackOpts;
+use self_addr::SelfAddrHints;
+use staging::OnConflict;
+use transfer::DataMode;
+use transfer::Fallback;
+use transfer::Transfer;
+
 use clap::App;
 use clap::Arg;
+use clap::ArgMatches;
 
 use anyhow::Context;
 use anyhow::Result;
@@ -12,46 +48,280 @@ use anyhow::Result;
 fn main() -> Result<()> {
     let migrate_cmd = App::new("migrate")
         .about("Migrate the chain data")
+        .arg(chain_dir_arg())
+        .arg(out_dir_arg())
+        .arg(chain_name_arg())
+        .args(chain_config_args())
+        .args(transfer_args())
         .arg(
-            Arg::new("chain-dir")
-                .about("The old chain dir")
-                .short('d')
-                .long("chain-dir")
+            Arg::new("jobs")
+                .about("Number of nodes migrated at the same time [default: number of CPUs]")
+                .short('j')
+                .long("jobs")
+                .takes_value(true)
+                .validator(str::parse::<usize>),
+        )
+        .args(progress_args())
+        .arg(on_conflict_arg())
+        .arg(force_arg())
+        .arg(
+            Arg::new("report")
+                .about("Write a json report of the migration, or of the plan with `--dry-run`")
+                .long("report")
                 .takes_value(true)
-                .required(true)
                 .validator(str::parse::<PathBuf>),
         )
         .arg(
-            Arg::new("out-dir")
-                .about("The output dir for the upgraded chain")
-                .short('o')
-                .long("out-dir")
+            Arg::new("dry-run")
+                .about("Print what would be done without writing anything")
+                .long("dry-run"),
+        );
+
+    let validate_cmd = App::new("validate")
+        .about("Check the old chain dir and report every problem found")
+        .arg(chain_dir_arg())
+        .arg(chain_name_arg())
+        .arg(node_dir_arg());
+
+    let prepare_cmd = App::new("prepare")
+        .about("Produce the chain-wide manifest for migrating nodes on separate machines")
+        .arg(chain_dir_arg())
+        .arg(chain_name_arg())
+        .arg(manifest_arg())
+        .args(chain_config_args());
+
+    let migrate_node_cmd = App::new("migrate-node")
+        .about("Migrate a single node with the manifest produced by `prepare`")
+        .arg(manifest_arg())
+        .arg(
+            Arg::new("node-dir")
+                .about("The old node dir")
+                .long("node-dir")
                 .takes_value(true)
                 .required(true)
                 .validator(str::parse::<PathBuf>),
         )
+        .arg(out_dir_arg())
+        .args(transfer_args())
+        .args(progress_args())
+        .arg(on_conflict_arg())
+        .arg(force_arg());
+
+    let presync_cmd = App::new("presync")
+        .about("Copy node data ahead of the migration while the old chain is still running")
+        .arg(chain_dir_arg())
+        .arg(out_dir_arg())
+        .arg(chain_name_arg())
+        .arg(node_dir_arg())
+        .arg(checksum_arg());
+
+    let verify_cmd = App::new("verify")
+        .about("Check the migrated chain against the old chain and report every difference found")
+        .arg(chain_dir_arg())
+        .arg(out_dir_arg())
+        .arg(chain_name_arg())
+        .arg(node_dir_arg())
+        .arg(authoritative_node_arg())
+        .arg(validator_keys_arg());
+
+    let backup_cmd = App::new("backup")
+        .about("Snapshot the old chain into a timestamped archive with a manifest")
+        .arg(chain_dir_arg())
+        .arg(chain_name_arg())
+        .arg(node_dir_arg())
+        .arg(
+            Arg::new("backup-dir")
+                .about("The dir to put the archive in")
+                .short('b')
+                .long("backup-dir")
+                .takes_value(true)
+                .default_value(".")
+                .validator(str::parse::<PathBuf>),
+        )
+        .arg(force_arg());
+
+    let restore_cmd = App::new("restore")
+        .about("Put the old chain back from an archive made by `backup`")
         .arg(
-            Arg::new("chain-name")
-                .about("Name of the chain")
-                .short('n')
-                .long("chain-name")
+            Arg::new("archive")
+                .about("The archive made by `backup`")
+                .short('a')
+                .long("archive")
                 .takes_value(true)
                 .required(true)
                 .validator(str::parse::<PathBuf>),
+        )
+        .arg(chain_dir_arg())
+        .arg(
+            Arg::new("on-conflict")
+                .about("What to do with dirs of the archive already in the chain dir")
+                .long("on-conflict")
+                .takes_value(true)
+                .possible_values(OnConflict::VARIANTS)
+                .default_value("fail"),
         );
 
+    let rollback_cmd = App::new("rollback")
+        .about("Rebuild the 6.1.0 node dirs from the upgraded chain")
+        .arg(chain_dir_arg())
+        .arg(out_dir_arg())
+        .arg(chain_name_arg())
+        .args(transfer_args())
+        .arg(
+            Arg::new("on-conflict")
+                .about("What to do with node dirs already in the chain dir")
+                .long("on-conflict")
+                .takes_value(true)
+                .possible_values(OnConflict::VARIANTS)
+                .default_value("fail"),
+        )
+        .arg(force_arg());
+
     let app = App::new("migration-tool")
         // It's surprising that a minor version bump results in a huge change.
         .about("migration tool for upgrading CITA-Cloud chain from 6.1.0 to 6.3.0")
-        .subcommand(migrate_cmd);
+        .subcommand(migrate_cmd)
+        .subcommand(validate_cmd)
+        .subcommand(prepare_cmd)
+        .subcommand(migrate_node_cmd)
+        .subcommand(presync_cmd)
+        .subcommand(verify_cmd)
+        .subcommand(backup_cmd)
+        .subcommand(restore_cmd)
+        .subcommand(rollback_cmd);
 
     match app.get_matches().subcommand() {
         Some(("migrate", m)) => {
             let chain_dir = m.value_of("chain-dir").unwrap();
             let out_dir = m.value_of("out-dir").unwrap();
             let chain_name = m.value_of("chain-name").unwrap();
+            let opts = MigrateOpts {
+                transfer: transfer_opts(m)?,
+                jobs: m.value_of("jobs").map(str::parse).transpose()?,
+                progress: progress_opts(m)?,
+                on_conflict: m.value_of("on-conflict").unwrap().parse()?,
+                force: m.is_present("force"),
+                report: m.value_of("report").map(PathBuf::from),
+                ..migrate_opts(m)?
+            };
+
+            if m.is_present("dry-run") {
+                let plan = migrate::dry_run(chain_dir, out_dir, chain_name, &opts)
+                    .context("cannot plan chain migration")?;
+                print!("{}", plan);
+            } else {
+                migrate::migrate(chain_dir, out_dir, chain_name, &opts)
+                    .context("cannot migrate chain")?;
+            }
+        }
+        Some(("validate", m)) => {
+            let chain_dir = m.value_of("chain-dir").unwrap();
+            let chain_name = m.value_of("chain-name").unwrap();
+
+            let node_dirs = values_of_paths(m, "node-dir");
+
+            validate::validate(chain_dir, chain_name, &node_dirs).context("invalid chain")?;
+            println!("chain `{}` is ready for migration", chain_name);
+        }
+        Some(("prepare", m)) => {
+            let chain_dir = m.value_of("chain-dir").unwrap();
+            let chain_name = m.value_of("chain-name").unwrap();
+            let manifest = m.value_of("manifest").unwrap();
+            let opts = migrate_opts(m)?;
+
+            distributed::prepare(chain_dir, manifest, chain_name, &opts)
+                .context("cannot prepare chain manifest")?;
+        }
+        Some(("migrate-node", m)) => {
+            let manifest = m.value_of("manifest").unwrap();
+            let node_dir = m.value_of("node-dir").unwrap();
+            let out_dir = m.value_of("out-dir").unwrap();
+
+            let transfer = transfer_opts(m)?;
+            let progress = progress_opts(m)?;
+            let on_conflict = m.value_of("on-conflict").unwrap().parse()?;
+            let force = m.is_present("force");
+
+            distributed::migrate_single_node(
+                manifest,
+                node_dir,
+                out_dir,
+                transfer,
+                progress,
+                on_conflict,
+                force,
+            )
+            .context("cannot migrate node")?;
+        }
+        Some(("presync", m)) => {
+            let chain_dir = m.value_of("chain-dir").unwrap();
+            let out_dir = m.value_of("out-dir").unwrap();
+            let chain_name = m.value_of("chain-name").unwrap();
+            let node_dirs = values_of_paths(m, "node-dir");
+
+            presync::presync(
+                chain_dir,
+                out_dir,
+                chain_name,
+                &node_dirs,
+                m.is_present("checksum"),
+            )
+            .context("cannot presync chain data")?;
+        }
+        Some(("verify", m)) => {
+            let chain_dir = m.value_of("chain-dir").unwrap();
+            let out_dir = m.value_of("out-dir").unwrap();
+            let chain_name = m.value_of("chain-name").unwrap();
+            let node_dirs = values_of_paths(m, "node-dir");
 
-            migrate::migrate(chain_dir, out_dir, chain_name).context("cannot migrate chain")?;
+            verify::verify(
+                chain_dir,
+                out_dir,
+                chain_name,
+                &node_dirs,
+                m.value_of("authoritative-node"),
+                &ValidatorKeys::load(m.value_of("validator-keys").map(Path::new))?,
+            )
+            .context("verification failed")?;
+            println!(
+                "chain `{}` in `{}` matches the old chain",
+                chain_name, out_dir
+            );
+        }
+        Some(("backup", m)) => {
+            let chain_dir = m.value_of("chain-dir").unwrap();
+            let chain_name = m.value_of("chain-name").unwrap();
+            let node_dirs = values_of_paths(m, "node-dir");
+            let backup_dir = m.value_of("backup-dir").unwrap();
+
+            backup::backup(
+                chain_dir,
+                chain_name,
+                &node_dirs,
+                backup_dir,
+                m.is_present("force"),
+            )
+            .context("cannot back up chain")?;
+        }
+        Some(("restore", m)) => {
+            let archive = m.value_of("archive").unwrap();
+            let chain_dir = m.value_of("chain-dir").unwrap();
+            let on_conflict = m.value_of("on-conflict").unwrap().parse()?;
+
+            backup::restore(archive, chain_dir, on_conflict).context("cannot restore chain")?;
+        }
+        Some(("rollback", m)) => {
+            let chain_dir = m.value_of("chain-dir").unwrap();
+            let out_dir = m.value_of("out-dir").unwrap();
+            let chain_name = m.value_of("chain-name").unwrap();
+            let opts = RollbackOpts {
+                transfer: transfer_opts(m)?,
+                on_conflict: m.value_of("on-conflict").unwrap().parse()?,
+                force: m.is_present("force"),
+            };
+
+            rollback::rollback(out_dir, chain_dir, chain_name, &opts)
+                .context("cannot roll back chain")?;
         }
         None => {
             println!("no subcommand provided");
@@ -63,3 +333,189 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn chain_dir_arg() -> Arg<'static> {
+    Arg::new("chain-dir")
+        .about("The old chain dir")
+        .short('d')
+        .long("chain-dir")
+        .takes_value(true)
+        .required(true)
+        .validator(str::parse::<PathBuf>)
+}
+
+fn out_dir_arg() -> Arg<'static> {
+    Arg::new("out-dir")
+        .about("The output dir for the upgraded chain")
+        .short('o')
+        .long("out-dir")
+        .takes_value(true)
+        .required(true)
+        .validator(str::parse::<PathBuf>)
+}
+
+fn chain_name_arg() -> Arg<'static> {
+    Arg::new("chain-name")
+        .about("Name of the chain")
+        .short('n')
+        .long("chain-name")
+        .takes_value(true)
+        .required(true)
+        .validator(str::parse::<PathBuf>)
+}
+
+fn node_dir_arg() -> Arg<'static> {
+    Arg::new("node-dir")
+        .about("The old node dirs to migrate, instead of discovering them in the chain dir")
+        .long("node-dir")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .validator(str::parse::<PathBuf>)
+}
+
+fn manifest_arg() -> Arg<'static> {
+    Arg::new("manifest")
+        .about("The chain-wide manifest file")
+        .short('m')
+        .long("manifest")
+        .takes_value(true)
+        .required(true)
+        .validator(str::parse::<PathBuf>)
+}
+
+// Args for migrating the chain configs, see `MigrateOpts`.
+fn chain_config_args() -> Vec<Arg<'static>> {
+    vec![
+        node_dir_arg(),
+        Arg::new("self-addr-file")
+            .about("A toml file mapping node index to its own `host:port`, e.g. `0 = \"192.168.1.10:40000\"`")
+            .long("self-addr-file")
+            .takes_value(true)
+            .validator(str::parse::<PathBuf>),
+        Arg::new("host")
+            .about("The host of all nodes, used with each node's network listen port as its own address")
+            .long("host")
+            .takes_value(true),
+        authoritative_node_arg(),
+        Arg::new("consensus")
+            .about("The consensus service of the new chain")
+            .long("consensus")
+            .takes_value(true)
+            .possible_values(ConsensusKind::VARIANTS)
+            .default_value("raft"),
+        validator_keys_arg(),
+        Arg::new("network")
+            .about("The network service of the new chain, `p2p` needs no certs")
+            .long("network")
+            .takes_value(true)
+            .possible_values(NetworkKind::VARIANTS)
+            .default_value("tls"),
+    ]
+}
+
+fn authoritative_node_arg() -> Arg<'static> {
+    Arg::new("authoritative-node")
+        .about("The node dir whose system config and genesis are used when nodes disagree")
+        .long("authoritative-node")
+        .takes_value(true)
+}
+
+fn validator_keys_arg() -> Arg<'static> {
+    Arg::new("validator-keys")
+        .about("A toml file mapping node address to its validator key, required by `overlord`")
+        .long("validator-keys")
+        .takes_value(true)
+        .validator(str::parse::<PathBuf>)
+}
+
+fn migrate_opts(m: &ArgMatches) -> Result<MigrateOpts> {
+    let self_addr_hints = SelfAddrHints::load(
+        m.value_of("self-addr-file").map(Path::new),
+        m.value_of("host").map(str::to_string),
+    )?;
+    Ok(MigrateOpts {
+        authoritative_node: m.value_of("authoritative-node").map(str::to_string),
+        node_dirs: values_of_paths(m, "node-dir"),
+        self_addr_hints,
+        consensus: m.value_of("consensus").unwrap().parse()?,
+        validator_keys: ValidatorKeys::load(m.value_of("validator-keys").map(Path::new))?,
+        network: m.value_of("network").unwrap().parse()?,
+        ..Default::default()
+    })
+}
+
+fn transfer_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("data-mode")
+            .about("How to transfer `chain_data`, `data` and `logs` to the new node dir")
+            .long("data-mode")
+            .takes_value(true)
+            .possible_values(DataMode::VARIANTS)
+            .default_value("copy"),
+        Arg::new("data-mode-fallback")
+            .about("What to do if the data mode doesn't work, e.g. hardlink across filesystems")
+            .long("data-mode-fallback")
+            .takes_value(true)
+            .possible_values(Fallback::VARIANTS)
+            .default_value("fail"),
+        checksum_arg(),
+    ]
+}
+
+fn checksum_arg() -> Arg<'static> {
+    Arg::new("checksum")
+        .about("Compare files by content hash instead of size and mtime when copying only changes")
+        .long("checksum")
+}
+
+fn transfer_opts(m: &ArgMatches) -> Result<Transfer> {
+    Ok(Transfer {
+        mode: m.value_of("data-mode").unwrap().parse()?,
+        fallback: m.value_of("data-mode-fallback").unwrap().parse()?,
+        checksum: m.is_present("checksum"),
+    })
+}
+
+fn progress_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("quiet")
+            .about("Don't report the progress")
+            .short('q')
+            .long("quiet")
+            .conflicts_with("progress"),
+        Arg::new("progress")
+            .about("How to report the progress, `json` prints a json object per line")
+            .long("progress")
+            .takes_value(true)
+            .possible_values(ProgressFormat::VARIANTS)
+            .default_value("text"),
+    ]
+}
+
+fn on_conflict_arg() -> Arg<'static> {
+    Arg::new("on-conflict")
+        .about("What to do with meta and node dirs already in the out dir")
+        .long("on-conflict")
+        .takes_value(true)
+        .possible_values(OnConflict::VARIANTS)
+        .default_value("fail")
+}
+
+fn force_arg() -> Arg<'static> {
+    Arg::new("force")
+        .about("Go on even if the chain seems to be running")
+        .long("force")
+}
+
+fn progress_opts(m: &ArgMatches) -> Result<Option<ProgressFormat>> {
+    if m.is_present("quiet") {
+        return Ok(None);
+    }
+    Ok(Some(m.value_of("progress").unwrap().parse()?))
+}
+
+fn values_of_paths(m: &ArgMatches, name: &str) -> Vec<PathBuf> {
+    m.values_of(name)
+        .map(|vs| vs.map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::plan::CopyKind;
+use crate::plan::CopyOp;
+use crate::transfer::sha256_file;
+use crate::transfer::DataMode;
+
+// Put in every new node dir and the new metadata dir.
+pub const MANIFEST_FILE: &str = "data-manifest.toml";
+
+#[derive(Serialize, Deserialize)]
+pub struct FileEntry {
+    // Relative to the dir holding the manifest.
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// Every file copied into a new node dir.
+#[derive(Serialize, Deserialize)]
+pub struct DataManifest {
+    pub node_address: String,
+    pub files: Vec<FileEntry>,
+}
+
+// Every file copied into the new metadata dir, and the digests of the node manifests.
+#[derive(Serialize, Deserialize)]
+pub struct ChainDataManifest {
+    pub chain_name: String,
+    // Before `files`, toml can't have an empty array after an array of tables.
+    pub nodes: Vec<NodeManifestDigest>,
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NodeManifestDigest {
+    pub node_address: String,
+    // Relative to the new chain dir.
+    pub manifest: PathBuf,
+    pub sha256: String,
+}
+
+impl DataManifest {
+    pub fn build(node_address: &str, new_dir: &Path, copies: &[CopyOp]) -> Result<Self> {
+        Ok(Self {
+            node_address: node_address.to_string(),
+            files: hash_copies(new_dir, copies)?,
+        })
+    }
+}
+
+impl ChainDataManifest {
+    pub fn build(
+        chain_name: &str,
+        new_chain_data_dir: &Path,
+        meta_dir: &Path,
+        meta_copies: &[CopyOp],
+        node_dirs: &[(&str, &Path)],
+    ) -> Result<Self> {
+        let nodes = node_dirs
+            .iter()
+            .map(|(node_address, node_dir)| {
+                let manifest = node_dir.join(MANIFEST_FILE);
+                Ok(NodeManifestDigest {
+                    node_address: node_address.to_string(),
+                    sha256: sha256_file(&manifest)?,
+                    manifest: manifest
+                        .strip_prefix(new_chain_data_dir)
+                        .unwrap_or(&manifest)
+                        .to_path_buf(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            files: hash_copies(meta_dir, meta_copies)?,
+            nodes,
+        })
+    }
+}
+
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let s = fs::read_to_string(path)
+        .with_context(|| format!("cannot read manifest `{}`", path.to_string_lossy()))?;
+    toml::from_str(&s).with_context(|| format!("invalid manifest `{}`", path.to_string_lossy()))
+}
+
+pub fn save<T: Serialize>(manifest: &T, path: &Path) -> Result<()> {
+    let content = toml::to_string_pretty(manifest).context("cannot serialize manifest")?;
+    fs::write(path, content)
+        .with_context(|| format!("cannot write manifest `{}`", path.to_string_lossy()))
+}
+
+// Check the files in `dir` against the manifest entries,
+// return the relative paths of those missing or changed, with the reason.
+pub fn check(dir: &Path, files: &[FileEntry]) -> Vec<(PathBuf, String)> {
+    files
+        .par_iter()
+        .filter_map(|f| {
+            let path = dir.join(&f.path);
+            let problem = match fs::metadata(&path) {
+                Err(_) => "missing".to_string(),
+                Ok(meta) if meta.len() != f.size => {
+                    format!("size {} instead of {}", meta.len(), f.size)
+                }
+                Ok(_) => match sha256_file(&path) {
+                    Ok(sha256) if sha256 == f.sha256 => return None,
+                    Ok(_) => "content changed".to_string(),
+                    Err(e) => format!("{:#}", e),
+                },
+            };
+            Some((f.path.clone(), problem))
+        })
+        .collect()
+}
+
+// Copies found not matching their sources, by their destinations, with what's wrong.
+#[derive(Debug)]
+pub struct CopyMismatch {
+    pub copies: Vec<PathBuf>,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for CopyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.problems.join("\n"))
+    }
+}
+
+impl std::error::Error for CopyMismatch {}
+
+// Hash every file copied into `new_dir`, in parallel. Only the copies are read, so hashing
+// adds a single pass over the data. Sources are checked by their listing and sizes, which
+// catches missing, extra and truncated copies right away, `verify` compares the contents.
+// Hardlinked and moved files are the sources themselves, there's nothing to compare with.
+// Every mismatch is collected into a `CopyMismatch`, so they can all be copied again.
+fn hash_copies(new_dir: &Path, copies: &[CopyOp]) -> Result<Vec<FileEntry>> {
+    let mut pairs = vec![];
+    let mut mismatched: Vec<(&CopyOp, String)> = vec![];
+    for op in copies {
+        let compare = !matches!(op.transfer.mode, DataMode::Hardlink | DataMode::Move);
+        match op.kind {
+            CopyKind::File => pairs.push((op, compare.then(|| op.from.clone()), op.to.clone())),
+            CopyKind::Dir => {
+                let copied = list_files(&op.to)?;
+                if compare && op.from.exists() {
+                    let sources = list_files(&op.from)?;
+                    if let Some(f) = sources.symmetric_difference(&copied).next() {
+                        mismatched.push((
+                            op,
+                            format!(
+                                "`{}` and `{}` differ in `{}`",
+                                op.from.to_string_lossy(),
+                                op.to.to_string_lossy(),
+                                f.to_string_lossy()
+                            ),
+                        ));
+                        continue;
+                    }
+                }
+                pairs.extend(
+                    copied
+                        .into_iter()
+                        .map(|f| (op, compare.then(|| op.from.join(&f)), op.to.join(&f))),
+                );
+            }
+        }
+    }
+
+    // The inner error is a mismatch, the outer one failing to read.
+    let hashed: Vec<(&CopyOp, Result<FileEntry, String>)> = pairs
+        .par_iter()
+        .map(|(op, from, to)| {
+            let size = fs::metadata(to)
+                .with_context(|| format!("cannot stat `{}`", to.to_string_lossy()))?
+                .len();
+            if let Some(from) = from {
+                if matches!(fs::metadata(from), Ok(source) if source.len() != size) {
+                    let problem = format!(
+                        "`{}` differs in size from its source `{}`",
+                        to.to_string_lossy(),
+                        from.to_string_lossy()
+                    );
+                    return Ok((*op, Err(problem)));
+                }
+            }
+            let entry = FileEntry {
+                path: to.strip_prefix(new_dir).unwrap_or(to).to_path_buf(),
+                size,
+                sha256: sha256_file(to)?,
+            };
+            Ok((*op, Ok(entry)))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut files = vec![];
+    for (op, res) in hashed {
+        match res {
+            Ok(entry) => files.push(entry),
+            Err(problem) => mismatched.push((op, problem)),
+        }
+    }
+    if !mismatched.is_empty() {
+        let copies: BTreeSet<PathBuf> = mismatched.iter().map(|(op, _)| op.to.clone()).collect();
+        return Err(CopyMismatch {
+            copies: copies.into_iter().collect(),
+            problems: mismatched.into_iter().map(|(_, p)| p).collect(),
+        }
+        .into());
+    }
+    Ok(files)
+}
+
+// Files under `dir`, relative to it.
+fn list_files(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    fn walk(dir: &Path, prefix: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+        let entries = fs::read_dir(dir)
+            .with_context(|| format!("cannot read dir `{}`", dir.to_string_lossy()))?;
+        for ent in entries {
+            let ent =
+                ent.with_context(|| format!("cannot read dir `{}`", dir.to_string_lossy()))?;
+            let rel = prefix.join(ent.file_name());
+            if ent.path().is_dir() {
+                walk(&ent.path(), &rel, files)?;
+            } else {
+                files.insert(rel);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = BTreeSet::new();
+    walk(dir, Path::new(""), &mut files)?;
+    Ok(files)
+}
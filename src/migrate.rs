@@ -1,30 +1,49 @@
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
 
-use fs_extra::dir::copy as copy_dir;
-use fs_extra::dir::CopyOptions;
-
+use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Context;
 use anyhow::Result;
 use serde::de::DeserializeOwned;
 
-use crate::cert::{generate_certs, CertAndKey};
-
-mod old {
+use crate::cert::{CertAndKey, KeyMaterial};
+use crate::consensus::{ConsensusKind, ValidatorKeys};
+use crate::consistency::Membership;
+use crate::detect::{detect_chain, Detected};
+use crate::inventory::inventory;
+use crate::journal::{shared_step, Journal, OutputOpts, SharedJournal};
+use crate::manifest::{
+    self, ChainDataManifest, CopyMismatch, DataManifest, FileEntry, MANIFEST_FILE,
+};
+use crate::network::NetworkKind;
+use crate::pipeline::{self, ChainModel, NewChain, OldChain, Registry, StepContext, Version};
+use crate::plan::{self, CopyOp, MetaPlan, MigrationPlan, NodePlan};
+use crate::progress::{NodeProgress, Progress, ProgressFormat};
+use crate::report::Report;
+use crate::running;
+use crate::self_addr::SelfAddrHints;
+use crate::staging::{self, OnConflict};
+use crate::transfer::{free_space, same_filesystem, DataMode, Fallback, Transfer};
+
+pub(crate) mod old {
     use serde::Deserialize;
+    use serde::Serialize;
 
-    #[derive(Deserialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct ConsensusConfig {
         pub controller_port: u16,
     }
 
-    #[derive(Deserialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct ControllerConfig {
         pub network_port: u16,
         pub consensus_port: u16,
@@ -33,19 +52,21 @@ mod old {
         pub executor_port: u16,
     }
 
-    #[derive(Deserialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct NetworkConfig {
         pub port: u16,
+        // Single node chain has no peers.
+        #[serde(default)]
         pub peers: Vec<PeerConfig>,
     }
 
-    #[derive(Deserialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct PeerConfig {
         pub ip: String,
         pub port: u16,
     }
 
-    #[derive(Deserialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct InitSysConfig {
         pub version: u64,
         pub admin: String,
@@ -54,20 +75,21 @@ mod old {
         pub validators: Vec<String>,
     }
 
-    #[derive(Deserialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct Genesis {
         pub timestamp: u64,
         pub prevhash: String,
     }
 }
 
-mod new {
+pub(crate) mod new {
+    use serde::Deserialize;
     use serde::Serialize;
 
     pub const DEFAULT_BLOCK_LIMIT: u64 = 100;
     pub const DEFAULT_PACKAGE_LIMIT: u64 = 30000;
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct ControllerConfig {
         pub consensus_port: u16,
         pub controller_port: u16,
@@ -81,7 +103,7 @@ mod new {
         pub package_limit: u64,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct ConsensusRaftConfig {
         pub controller_port: u16,
         pub grpc_listen_port: u16,
@@ -89,13 +111,46 @@ mod new {
         pub node_addr: String,
     }
 
-    #[derive(Serialize, Clone)]
+    #[derive(Serialize, Deserialize)]
+    pub struct ConsensusBftConfig {
+        pub consensus_port: u16,
+        pub controller_port: u16,
+        pub kms_port: u16,
+        pub network_port: u16,
+
+        pub key_id: u64,
+        pub node_address: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ConsensusOverlordConfig {
+        pub consensus_port: u16,
+        pub controller_port: u16,
+        pub kms_port: u16,
+        pub network_port: u16,
+
+        pub key_id: u64,
+        pub node_address: String,
+    }
+
+    // The section is named after the consensus service.
+    #[derive(Serialize, Deserialize)]
+    pub enum Consensus {
+        #[serde(rename = "consensus_raft")]
+        Raft(ConsensusRaftConfig),
+        #[serde(rename = "consensus_bft")]
+        Bft(ConsensusBftConfig),
+        #[serde(rename = "consensus_overlord")]
+        Overlord(ConsensusOverlordConfig),
+    }
+
+    #[derive(Serialize, Deserialize, Clone, PartialEq)]
     pub struct GenesisBlock {
         pub prevhash: String,
         pub timestamp: u64,
     }
 
-    #[derive(Serialize, Clone)]
+    #[derive(Serialize, Deserialize, Clone, PartialEq)]
     pub struct SystemConfig {
         pub admin: String,
         pub block_interval: u64,
@@ -105,7 +160,7 @@ mod new {
         pub validators: Vec<String>,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct NetworkTlsConfig {
         // Optional fields will be filled latter
         pub ca_cert: Option<String>,
@@ -115,7 +170,7 @@ mod new {
         pub peers: Vec<NetworkTlsPeerConfig>,
     }
 
-    #[derive(Serialize, Clone)]
+    #[derive(Serialize, Deserialize, Clone)]
     pub struct NetworkTlsPeerConfig {
         // Will be filled latter
         pub domain: Option<String>,
@@ -123,40 +178,63 @@ mod new {
         pub port: u16,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
+    pub struct NetworkP2pConfig {
+        pub grpc_port: u16,
+        // listen network peers' connections
+        pub port: u16,
+        pub peers: Vec<NetworkP2pPeerConfig>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct NetworkP2pPeerConfig {
+        // multiaddr, e.g. `/ip4/127.0.0.1/tcp/40000`
+        pub address: String,
+    }
+
+    // The section is named after the network service.
+    #[derive(Serialize, Deserialize)]
+    pub enum Network {
+        #[serde(rename = "network_tls")]
+        Tls(NetworkTlsConfig),
+        #[serde(rename = "network_p2p")]
+        P2p(NetworkP2pConfig),
+    }
+
+    #[derive(Serialize, Deserialize)]
     pub struct KmsSmConfig {
         pub kms_port: u16,
         pub db_key: String,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct StorageRocksDbConfig {
         pub kms_port: u16,
         pub storage_port: u16,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct ExecutorEvmConfig {
         pub executor_port: u16,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct Config {
         pub system_config: SystemConfig,
         pub genesis_block: GenesisBlock,
 
         #[serde(rename = "controller")]
         pub controller: ControllerConfig,
-        #[serde(rename = "consensus_raft")]
-        pub consensus: ConsensusRaftConfig,
+        #[serde(flatten)]
+        pub consensus: Consensus,
         #[serde(rename = "storage_rocksdb")]
         pub storage: StorageRocksDbConfig,
         #[serde(rename = "executor_evm")]
         pub executor: ExecutorEvmConfig,
         #[serde(rename = "kms_sm")]
         pub kms: KmsSmConfig,
-        #[serde(rename = "network_tls")]
-        pub network: NetworkTlsConfig,
+        #[serde(flatten)]
+        pub network: Network,
 
         // Helper data, will be filled later
         #[serde(skip)]
@@ -165,10 +243,10 @@ mod new {
         pub network_port: Option<u16>,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct MetaConfig {
-        #[serde(rename = "network_tls")]
-        pub network: MetaNetworkConfig,
+        #[serde(flatten)]
+        pub network: MetaNetwork,
 
         pub genesis_block: GenesisBlock,
         pub system_config: SystemConfig,
@@ -178,18 +256,21 @@ mod new {
         pub current_config: MetaCurrentConfig,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct MetaAdminConfig {
         pub admin_address: String,
         pub key_id: u64,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct MetaCurrentConfig {
         pub addresses: Vec<String>,
 
-        pub ca_cert_pem: String,
-        pub ca_key_pem: String,
+        // No CA without `network_tls`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ca_cert_pem: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ca_key_pem: Option<String>,
 
         pub count: u64,
 
@@ -200,47 +281,67 @@ mod new {
         // Always false
         pub use_num: bool,
 
-        pub tls_peers: MetaNetworkConfig,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub tls_peers: Option<MetaNetworkConfig>,
     }
 
-    #[derive(Serialize, Clone)]
+    #[derive(Serialize, Deserialize, Clone)]
     pub struct MetaNetworkConfig {
         pub peers: Vec<NetworkTlsPeerConfig>,
     }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct MetaNetworkP2pConfig {
+        pub peers: Vec<NetworkP2pPeerConfig>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    pub enum MetaNetwork {
+        #[serde(rename = "network_tls")]
+        Tls(MetaNetworkConfig),
+        #[serde(rename = "network_p2p")]
+        P2p(MetaNetworkP2pConfig),
+    }
 }
 
-struct NodeConfigMigrate {
+pub(crate) struct NodeConfigMigrate {
     // node config loaded from old
 
     // ports
-    controller_port: u16,
-    consensus_port: u16,
-    executor_port: u16,
-    network_port: u16,
-    kms_port: u16,
-    storage_port: u16,
+    pub(crate) controller_port: u16,
+    pub(crate) consensus_port: u16,
+    pub(crate) executor_port: u16,
+    pub(crate) network_port: u16,
+    pub(crate) kms_port: u16,
+    pub(crate) storage_port: u16,
 
     // controller
-    node_addr: String,
-    genesis_block: old::Genesis,
-    system_config: old::InitSysConfig,
+    pub(crate) node_addr: String,
+    pub(crate) genesis_block: old::Genesis,
+    pub(crate) system_config: old::InitSysConfig,
 
     // kms
-    kms_password: String,
-    key_id: u64,
+    pub(crate) kms_password: String,
+    pub(crate) key_id: u64,
 
     // network
-    network_config: old::NetworkConfig,
+    pub(crate) network_config: old::NetworkConfig,
 }
 
 impl NodeConfigMigrate {
-    pub fn from_old(data_dir: impl AsRef<Path>) -> Result<new::Config> {
-        let old =
-            Self::extract_from(data_dir).context("cannot extract info from old node config")?;
-        Ok(old.generate_new())
-    }
+    // Files read by `extract_from`.
+    pub const CONFIG_FILES: [&'static str; 8] = [
+        "controller-config.toml",
+        "consensus-config.toml",
+        "network-config.toml",
+        "node_address",
+        "init_sys_config.toml",
+        "genesis.toml",
+        "key_id",
+        "key_file",
+    ];
 
-    fn extract_from(data_dir: impl AsRef<Path>) -> Result<Self> {
+    pub fn extract_from(data_dir: impl AsRef<Path>) -> Result<Self> {
         let old::ControllerConfig {
             consensus_port,
             storage_port,
@@ -285,7 +386,7 @@ impl NodeConfigMigrate {
         Ok(this)
     }
 
-    fn generate_new(&self) -> new::Config {
+    pub fn generate_new(&self, consensus: ConsensusKind, network: NetworkKind) -> new::Config {
         let genesis_block = new::GenesisBlock {
             prevhash: self.genesis_block.prevhash.clone(),
             timestamp: self.genesis_block.timestamp,
@@ -313,12 +414,7 @@ impl NodeConfigMigrate {
             package_limit: new::DEFAULT_PACKAGE_LIMIT,
         };
 
-        let consensus = new::ConsensusRaftConfig {
-            controller_port: self.controller_port,
-            network_port: self.network_port,
-            node_addr: self.node_addr.clone(),
-            grpc_listen_port: self.consensus_port,
-        };
+        let consensus = consensus.generate(&controller);
 
         let kms = new::KmsSmConfig {
             kms_port: self.kms_port,
@@ -334,31 +430,13 @@ impl NodeConfigMigrate {
             executor_port: self.executor_port,
         };
 
-        let network = {
-            let peers = self
-                .network_config
-                .peers
-                .iter()
-                .map(|p| {
-                    new::NetworkTlsPeerConfig {
-                        // will be filled latter
-                        domain: None,
-                        host: p.ip.clone(),
-                        port: p.port,
-                    }
-                })
-                .collect();
-
-            new::NetworkTlsConfig {
-                // will be filled latter
-                ca_cert: None,
-                cert: None,
-                grpc_port: self.network_port,
-                // listen network peers' connections
-                listen_port: self.network_config.port,
-                peers,
-            }
-        };
+        let peers: Vec<(String, u16)> = self
+            .network_config
+            .peers
+            .iter()
+            .map(|p| (p.ip.clone(), p.port))
+            .collect();
+        let network = network.generate(self.network_port, self.network_config.port, &peers);
 
         new::Config {
             system_config,
@@ -377,14 +455,17 @@ impl NodeConfigMigrate {
     }
 }
 
-fn extract_toml<T: DeserializeOwned>(data_dir: impl AsRef<Path>, file_name: &str) -> Result<T> {
+pub(crate) fn extract_toml<T: DeserializeOwned>(
+    data_dir: impl AsRef<Path>,
+    file_name: &str,
+) -> Result<T> {
     let s = extract_text(data_dir, file_name).context("cannot load toml file")?;
     let res: T = toml::from_str(&s)
         .with_context(|| format!("invalid toml for the `{}` type", std::any::type_name::<T>()))?;
     Ok(res)
 }
 
-fn extract_text(data_dir: impl AsRef<Path>, file_name: &str) -> Result<String> {
+pub(crate) fn extract_text(data_dir: impl AsRef<Path>, file_name: &str) -> Result<String> {
     let path = data_dir.as_ref().join(file_name);
     let mut f = File::open(&path).with_context(|| {
         format!(
@@ -399,61 +480,37 @@ fn extract_text(data_dir: impl AsRef<Path>, file_name: &str) -> Result<String> {
     Ok(buf)
 }
 
-// Return CA's cert and key
-fn fill_network_tls_info(node_configs: &mut [new::Config]) -> Result<CertAndKey> {
-    // Construct (host, port) -> node_addr map.
-    let host_port_to_addr: HashMap<(String, u16), String> = {
-        let full_peer_set = {
-            let mut full_peer_set = HashSet::<(String, u16)>::new();
-            // Every node contains host and port for peers execept itself.
-            // So we can construct the full set with two configs.
-            for c in node_configs.iter().take(2) {
-                for p in &c.network.peers {
-                    full_peer_set.insert((p.host.clone(), p.port));
-                }
-            }
-            full_peer_set
-        };
-
-        // Find nodes' host and port
-        node_configs
-            .iter_mut()
-            .map(|c| {
-                let peer_set: HashSet<(String, u16)> = c
-                    .network
-                    .peers
-                    .iter()
-                    .map(|p| (p.host.clone(), p.port))
-                    .collect();
-                let (host, port) = full_peer_set.difference(&peer_set).next().cloned()
-                    .context(
-                        "Cannot find out node's self host and port. \
-                        The assumption that node's peers info contains all (and only) other peers has been violated"
-                    )?;
-                c.network_host.replace(host.clone());
-                c.network_port.replace(port);
-
-                Ok(
-                    ((host, port), c.controller.node_address.clone())
-                )
-            })
-            .collect::<Result<_>>()?
-    };
-
+// Fill CA cert, node's own cert signed by the CA and peers' domain.
+// Certs in `keys` are reused, missing ones are generated and put into it.
+// Nodes on another network service than `network_tls` have nothing to fill.
+pub(crate) fn fill_network_tls_certs(
+    node_configs: &mut [new::Config],
+    keys: &mut KeyMaterial,
+    host_port_to_addr: &HashMap<(String, u16), String>,
+) -> Result<()> {
     let node_addrs: Vec<String> = node_configs
         .iter()
+        .filter(|c| c.network.kind().needs_certs())
         .map(|c| c.controller.node_address.clone())
         .collect();
-    let (ca_cert_and_key, peer_cert_and_keys) = generate_certs(&node_addrs);
+    if node_addrs.is_empty() {
+        return Ok(());
+    }
+    let ca_cert_and_key = keys.ca_or_generate();
+    let peer_cert_and_keys = keys.certs_or_generate(&node_addrs)?;
 
     node_configs
         .iter_mut()
+        .filter_map(|c| match &mut c.network {
+            new::Network::Tls(network) => Some(network),
+            _ => None,
+        })
         .zip(peer_cert_and_keys)
-        .try_for_each(|(c, cert_and_key)| {
-            c.network.ca_cert.replace(ca_cert_and_key.cert.clone());
-            c.network.cert.replace(cert_and_key.cert);
+        .try_for_each(|(network, cert_and_key)| {
+            network.ca_cert.replace(ca_cert_and_key.cert.clone());
+            network.cert.replace(cert_and_key.cert);
 
-            for p in c.network.peers.iter_mut() {
+            for p in network.peers.iter_mut() {
                 let node_addr = host_port_to_addr
                     .get(&(p.host.clone(), p.port))
                     .cloned()
@@ -468,231 +525,891 @@ fn fill_network_tls_info(node_configs: &mut [new::Config]) -> Result<CertAndKey>
             Ok::<(), anyhow::Error>(())
         })?;
 
-    Ok(ca_cert_and_key)
+    Ok(())
+}
+
+pub(crate) struct NodeDirs {
+    pub dirs: Vec<PathBuf>,
+    // Dirs that look like node dirs but are not, with the reason.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+// Load node dirs, sorted by their node_id.
+// If `explicit_node_dirs` is not empty, use them as is instead of discovering.
+pub(crate) fn load_node_dirs(
+    chain_data_dir: &Path,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+) -> Result<NodeDirs> {
+    if !explicit_node_dirs.is_empty() {
+        for d in explicit_node_dirs {
+            ensure!(d.is_dir(), "node dir `{}` not found", d.to_string_lossy());
+        }
+        return Ok(NodeDirs {
+            dirs: explicit_node_dirs.to_vec(),
+            skipped: vec![],
+        });
+    }
+
+    let prefix = format!("{}-", chain_name);
+    let mut node_dirs: Vec<(u64, PathBuf)> = vec![];
+    let mut skipped = vec![];
+    let entries = fs::read_dir(chain_data_dir).with_context(|| {
+        format!(
+            "cannot read chain data folder `{}`",
+            chain_data_dir.to_string_lossy()
+        )
+    })?;
+    for ent in entries {
+        let ent = ent.with_context(|| {
+            format!(
+                "cannot read entry in chain data folder `{}`",
+                chain_data_dir.to_string_lossy()
+            )
+        })?;
+        let path = ent.path();
+        let dir_name = match ent.file_name().into_string() {
+            Ok(dir_name) => dir_name,
+            Err(_) => {
+                skipped.push((path, "non UTF-8 name".to_string()));
+                continue;
+            }
+        };
+        let suffix = match dir_name.strip_prefix(&prefix) {
+            Some(suffix) => suffix,
+            None => continue,
+        };
+        let file_type = ent
+            .file_type()
+            .with_context(|| format!("cannot get file type of `{}`", path.to_string_lossy()))?;
+        if !file_type.is_dir() {
+            skipped.push((path, "not a dir".to_string()));
+            continue;
+        }
+        match suffix.parse::<u64>() {
+            Ok(node_id) => node_dirs.push((node_id, path)),
+            Err(_) => skipped.push((path, format!("`{}` is not a node id", suffix))),
+        }
+    }
+
+    // Sort node dirs according to their node_id.
+    node_dirs.sort_by_key(|(node_id, _)| *node_id);
+    skipped.sort();
+
+    Ok(NodeDirs {
+        dirs: node_dirs.into_iter().map(|(_, d)| d).collect(),
+        skipped,
+    })
+}
+
+#[derive(Default)]
+pub struct MigrateOpts {
+    // Name of the node dir whose system config and genesis will be used
+    // when nodes disagree.
+    pub authoritative_node: Option<String>,
+    // Node dirs to migrate instead of discovering them in the chain dir.
+    pub node_dirs: Vec<PathBuf>,
+    // How to find out node's own host and port.
+    pub self_addr_hints: SelfAddrHints,
+    // How to transfer `chain_data`, `data` and `logs`.
+    pub transfer: Transfer,
+    // Number of nodes migrated at the same time, defaults to the number of CPUs.
+    pub jobs: Option<usize>,
+    // `None` for quiet.
+    pub progress: Option<ProgressFormat>,
+    // What to do with meta and node dirs already in the out dir.
+    pub on_conflict: OnConflict,
+    // Migrate even if the old chain seems to be running.
+    pub force: bool,
+    // Where to write the json report.
+    pub report: Option<PathBuf>,
+    // The consensus of the new chain.
+    pub consensus: ConsensusKind,
+    // The network service of the new chain.
+    pub network: NetworkKind,
+    // Validator keys replacing node addresses for consensus that needs them.
+    pub validator_keys: ValidatorKeys,
 }
 
-pub fn migrate<P, Q>(chain_data_dir: P, new_chain_data_dir: Q, chain_name: &str) -> Result<()>
+impl MigrateOpts {
+    // What a resumed migration must agree on with the interrupted one.
+    fn output_opts(&self) -> OutputOpts {
+        OutputOpts {
+            consensus: self.consensus,
+            network: self.network,
+            data_mode: self.transfer.mode,
+            authoritative_node: self.authoritative_node.clone(),
+            host: self.self_addr_hints.host.clone(),
+            self_addrs: self
+                .self_addr_hints
+                .mapping
+                .iter()
+                .map(|(index, (host, port))| (index.to_string(), format!("{}:{}", host, port)))
+                .collect(),
+            validator_keys: self.validator_keys.keys.clone().into_iter().collect(),
+        }
+    }
+}
+
+pub fn migrate<P, Q>(
+    chain_data_dir: P,
+    new_chain_data_dir: Q,
+    chain_name: &str,
+    opts: &MigrateOpts,
+) -> Result<()>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
+    let start = Instant::now();
     let chain_data_dir = chain_data_dir.as_ref();
-    let chain_metadata_dir = chain_data_dir.join(chain_name);
-    ensure!(chain_data_dir.is_dir(), "chain data folder not found");
-    ensure!(chain_metadata_dir.is_dir(), "metadata folder not found");
-
     let new_chain_data_dir = new_chain_data_dir.as_ref();
-    let new_chain_metadata_dir = new_chain_data_dir.join(chain_name);
-    fs::create_dir_all(&new_chain_data_dir).unwrap();
-    fs::create_dir_all(&new_chain_metadata_dir).unwrap();
-
-    // Load node dirs.
-    let mut node_dirs: Vec<PathBuf> = fs::read_dir(chain_data_dir)
-        .unwrap()
-        .filter_map(|ent| {
-            let ent = ent.unwrap();
-            let dir_name = ent.file_name().into_string().unwrap();
-            let prefix = format!("{}-", chain_name);
-            if ent.file_type().unwrap().is_dir() && dir_name.starts_with(&prefix) {
-                Some(ent.path())
-            } else {
-                None
+    let staging_dir = staging::staging_dir(new_chain_data_dir)?;
+
+    let planned = || -> Result<(Journal, MigrationPlan)> {
+        staging::ensure_presync_picked_up(&staging_dir, opts.transfer.mode)?;
+        // Reuse the key material and skip completed steps if resuming.
+        let mut journal =
+            Journal::load_or_new(&staging_dir, chain_data_dir, chain_name, opts.output_opts())?;
+        if journal.keys.ca.is_none() && opts.on_conflict == OnConflict::Skip {
+            if let Some(ca) = kept_ca(new_chain_data_dir, chain_name)? {
+                journal.keys = KeyMaterial::with_ca(ca);
             }
-        })
-        .collect();
+        }
+        let new_ca = journal.keys.ca.is_none();
+        let mut plan = plan(
+            chain_data_dir,
+            &staging_dir,
+            chain_name,
+            opts,
+            &mut journal.keys,
+        )?;
+        plan.resolve_conflicts(new_chain_data_dir);
+        ensure_kept_dirs_trust_ca(&plan, new_ca)?;
+        journal.record("plan", start, false);
+        Ok((journal, plan))
+    };
+    let (mut journal, plan) = match planned() {
+        Ok(planned) => planned,
+        Err(e) => {
+            // Nothing has been planned, the report only tells what failed.
+            if let Some(path) = &opts.report {
+                let mut report = Report::unplanned(chain_name, chain_data_dir, new_chain_data_dir);
+                report.total_seconds = start.elapsed().as_secs_f64();
+                report.error = Some(format!("{:#}", e));
+                if let Err(report_err) = report.write(path) {
+                    eprintln!("warning: {:#}", report_err);
+                }
+            }
+            return Err(e);
+        }
+    };
 
-    // Sort node dirs according to their node_id.
-    node_dirs.sort_by_key(|d| {
-        let dir_name = d.file_name().unwrap().to_string_lossy();
-        let node_id: u64 = dir_name
-            .strip_prefix(&format!("{}-", chain_name))
-            .unwrap()
-            .parse()
-            .unwrap();
-        node_id
-    });
+    let mut run = || -> Result<()> {
+        if plan.on_conflict == OnConflict::Fail {
+            staging::ensure_no_conflicts(&plan.conflicts)?;
+        }
+        // Every node dir is kept by `--on-conflict skip`.
+        if plan.nodes.is_empty() {
+            eprintln!(
+                "nothing to migrate, every node dir already exists in `{}`",
+                new_chain_data_dir.to_string_lossy()
+            );
+            return Ok(());
+        }
+        plan::ensure_enough_space(plan.space_needed(), plan.free_space, new_chain_data_dir)?;
+        for w in plan.warnings() {
+            eprintln!("warning: {}", w);
+        }
+        running::ensure_stopped(
+            plan.nodes.iter().map(|n| (n.old_dir.as_path(), &n.config)),
+            opts.force,
+        )?;
+        journal.save()?;
+        if let Err(e) = execute(&plan, new_chain_data_dir, &mut journal, opts) {
+            staging::discard(
+                &staging_dir,
+                staging::keep_reason(&staging_dir, opts.transfer.mode),
+            );
+            return Err(e);
+        }
+        let commit_start = Instant::now();
+        staging::commit(&staging_dir, new_chain_data_dir, opts.on_conflict)?;
+        journal.record("commit", commit_start, false);
+        Ok(())
+    };
+    let res = run();
+
+    // Report failures too, a report that can't be written doesn't hide them.
+    let report_res = match &opts.report {
+        Some(path) => Report::new(&plan, new_chain_data_dir, false).and_then(|mut report| {
+            report.set_steps(&journal.timings, &staging_dir);
+            report.total_seconds = start.elapsed().as_secs_f64();
+            report.error = res.as_ref().err().map(|e| format!("{:#}", e));
+            report.write(path)
+        }),
+        None => Ok(()),
+    };
+    res.and(report_res)
+}
 
-    // Construct new node config from the old one. (without network_tls info)
-    let mut node_configs = node_dirs
+// The CA of the meta dir kept by `--on-conflict skip`, which the kept nodes trust.
+fn kept_ca(new_chain_data_dir: &Path, chain_name: &str) -> Result<Option<CertAndKey>> {
+    let meta_dir = new_chain_data_dir.join(chain_name);
+    if !meta_dir.join("config.toml").exists() {
+        return Ok(None);
+    }
+    let meta_config: new::MetaConfig = extract_toml(&meta_dir, "config.toml")?;
+    match (
+        meta_config.current_config.ca_cert_pem,
+        meta_config.current_config.ca_key_pem,
+    ) {
+        (Some(cert), Some(key)) => Ok(Some(CertAndKey { cert, key })),
+        _ => Ok(None),
+    }
+}
+
+// Dirs kept by `--on-conflict skip` only trust the CA of the kept meta dir. If there's
+// none to load, the new nodes get certs of a new CA, and the chain would be split.
+fn ensure_kept_dirs_trust_ca(plan: &MigrationPlan, new_ca: bool) -> Result<()> {
+    let tls = plan.meta.config.current_config.ca_cert_pem.is_some();
+    if !new_ca || !tls || plan.on_conflict != OnConflict::Skip || plan.conflicts.is_empty() {
+        return Ok(());
+    }
+    let list: Vec<String> = plan
+        .conflicts
         .iter()
-        .map(|d| {
-            NodeConfigMigrate::from_old(d)
-                .with_context(|| format!("cannot migrate node config in `{}`", d.to_string_lossy()))
-        })
-        .collect::<Result<Vec<new::Config>>>()?;
+        .map(|d| format!("  `{}`", d.to_string_lossy()))
+        .collect();
+    bail!(
+        "cannot keep these dirs, no CA found in the meta dir `{}` to sign certs they trust, \
+        use `--on-conflict overwrite` instead:\n{}",
+        plan.chain_name,
+        list.join("\n")
+    )
+}
+
+pub(crate) struct ChainConfigs {
+    pub node_dirs: Vec<PathBuf>,
+    // Dirs that look like node dirs but are skipped, with the reason.
+    pub skipped_dirs: Vec<(PathBuf, String)>,
+    pub node_configs: Vec<new::Config>,
+    pub meta_config: new::MetaConfig,
+    pub membership: Membership,
+    // The version the chain is detected at.
+    pub source: Detected,
+    // The versions the chain goes through, e.g. `6.1.0 -> 6.3.0`.
+    pub migration_path: String,
+}
+
+// Migrate configs of the whole chain, without touching any data.
+pub(crate) fn migrate_configs(
+    chain_data_dir: &Path,
+    chain_name: &str,
+    opts: &MigrateOpts,
+    keys: &mut KeyMaterial,
+) -> Result<ChainConfigs> {
+    ensure!(chain_data_dir.is_dir(), "chain data folder not found");
+    // Only 6.3.0 chains can be written.
+    let target = Version::V6_3_0;
+    let source = detect_chain(chain_data_dir, chain_name, &opts.node_dirs)
+        .context("cannot detect the version of the chain")?;
+    ensure!(
+        source.version != target,
+        "`{}` is already at {}, nothing to migrate, detected from {}",
+        chain_data_dir.to_string_lossy(),
+        source.version,
+        source.evidence
+    );
+    let registry = Registry::builtin();
+    let steps = registry.plan(source.version, target)?;
+
+    let NodeDirs {
+        dirs: node_dirs,
+        skipped: skipped_dirs,
+    } = load_node_dirs(chain_data_dir, chain_name, &opts.node_dirs)
+        .context("cannot load node dirs")?;
+    let chain = match source.version {
+        Version::V6_1_0 => ChainModel::V6_1_0(load_old_chain(
+            chain_data_dir,
+            chain_name,
+            node_dirs.clone(),
+        )?),
+        v => bail!("cannot load a {} chain", v),
+    };
 
-    // Fill the network_tls info.
-    let CertAndKey {
-        cert: ca_cert_pem,
-        key: ca_key_pem,
-    } = fill_network_tls_info(&mut node_configs)
-        .context("cannot fill network_tls info for chain config")?;
+    let mut ctx = StepContext { opts, keys };
+    let NewChain {
+        node_configs,
+        meta_config,
+    } = match pipeline::run(&steps, chain, &mut ctx)? {
+        ChainModel::V6_3_0(new_chain) => new_chain,
+        other => bail!("cannot write a {} chain", other.version()),
+    };
 
-    // Construct $NEW_CHAIN_DATA_DIR/$CHAIN_NAME/config.toml
-    let meta_config = {
+    let membership = {
         let node_addrs: Vec<String> = node_configs
             .iter()
             .map(|c| c.controller.node_address.clone())
             .collect();
-        // Sample node
-        let first_node = node_configs
-            .first()
-            .context("Empty chain. No node config found")?;
-        let system_config = first_node.system_config.clone();
-        let genesis_block = first_node.genesis_block.clone();
-
-        let network_config = {
-            let itself = new::NetworkTlsPeerConfig {
-                domain: Some(first_node.controller.node_address.clone()),
-                // Network info has been filled.
-                host: first_node.network_host.clone().unwrap(),
-                port: first_node.network_port.unwrap(),
-            };
-            let peers: Vec<new::NetworkTlsPeerConfig> = std::iter::once(itself)
-                .chain(first_node.network.peers.clone())
-                .collect();
-
-            new::MetaNetworkConfig { peers }
-        };
-
-        let current_config = {
-            let (ips, p2p_ports) = network_config
-                .peers
-                .iter()
-                .map(|p| (p.host.clone(), p.port))
-                .unzip();
-
-            let rpc_ports = node_configs
-                .iter()
-                .map(|c| c.controller.controller_port)
-                .collect();
+        // Membership is about node addresses, not validator keys.
+        let validators = opts
+            .validator_keys
+            .addresses_of(&meta_config.system_config.validators);
+        Membership::new(&validators, &node_addrs)
+    };
 
-            new::MetaCurrentConfig {
-                addresses: node_addrs,
-                ca_cert_pem,
-                ca_key_pem,
-                count: node_configs.len() as u64,
+    Ok(ChainConfigs {
+        node_dirs,
+        skipped_dirs,
+        node_configs,
+        meta_config,
+        membership,
+        source,
+        migration_path: pipeline::describe(&steps),
+    })
+}
 
-                ips,
-                p2p_ports,
-                rpc_ports,
+// Read the configs of every old node into memory.
+fn load_old_chain(
+    chain_data_dir: &Path,
+    chain_name: &str,
+    node_dirs: Vec<PathBuf>,
+) -> Result<OldChain> {
+    let chain_metadata_dir = chain_data_dir.join(chain_name);
+    ensure!(chain_metadata_dir.is_dir(), "metadata folder not found");
+    ensure!(!node_dirs.is_empty(), "Empty chain. No node dir found");
 
-                use_num: false,
-                tls_peers: network_config.clone(),
-            }
-        };
+    let nodes = node_dirs
+        .iter()
+        .map(|d| {
+            NodeConfigMigrate::extract_from(d)
+                .context("cannot extract info from old node config")
+                .with_context(|| format!("cannot migrate node config in `{}`", d.to_string_lossy()))
+        })
+        .collect::<Result<Vec<NodeConfigMigrate>>>()?;
+
+    // Which admin is used is up to the migration, a missing key is only an error for that one.
+    let mut admin_key_ids = HashMap::new();
+    for node in &nodes {
+        let admin = &node.system_config.admin;
+        let admin_dir = chain_metadata_dir.join(admin);
+        if admin_key_ids.contains_key(admin) || !admin_dir.join("key_id").exists() {
+            continue;
+        }
+        let key_id = extract_text(&admin_dir, "key_id")
+            .context("cannot load admin `key_id`")?
+            .parse()
+            .with_context(|| format!("invalid `key_id` of admin `{}`", admin))?;
+        admin_key_ids.insert(admin.clone(), key_id);
+    }
 
-        let admin_config = {
-            let admin_address = first_node.system_config.admin.clone();
-            let key_id = {
-                let admin_key_dir = chain_metadata_dir.join(&admin_address);
-                extract_text(admin_key_dir, "key_id")
-                    .context("cannot load admin `key_id`")?
-                    .parse()
-                    .context("invalid admin `key_id`")?
-            };
-            new::MetaAdminConfig {
-                admin_address,
-                key_id,
-            }
-        };
+    Ok(OldChain {
+        node_dirs,
+        nodes,
+        admin_key_ids,
+    })
+}
 
-        new::MetaConfig {
-            network: network_config,
-            genesis_block,
-            system_config,
-            admin_config,
-            current_config,
+// Plan the migration, reusing the key material in the journal if any.
+pub fn dry_run<P, Q>(
+    chain_data_dir: P,
+    new_chain_data_dir: Q,
+    chain_name: &str,
+    opts: &MigrateOpts,
+) -> Result<MigrationPlan>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    // Show the same certs as a resumed migration would use.
+    let staging_dir = staging::staging_dir(new_chain_data_dir.as_ref())?;
+    let mut keys = Journal::load(staging_dir)?
+        .map(|j| j.keys)
+        .unwrap_or_default();
+    if keys.ca.is_none() && opts.on_conflict == OnConflict::Skip {
+        if let Some(ca) = kept_ca(new_chain_data_dir.as_ref(), chain_name)? {
+            keys = KeyMaterial::with_ca(ca);
         }
-    };
+    }
+    let new_ca = keys.ca.is_none();
+    let mut plan = plan(
+        chain_data_dir,
+        new_chain_data_dir.as_ref(),
+        chain_name,
+        opts,
+        &mut keys,
+    )?;
+    plan.resolve_conflicts(new_chain_data_dir.as_ref());
+    ensure_kept_dirs_trust_ca(&plan, new_ca)?;
+    if let Some(path) = &opts.report {
+        Report::new(&plan, new_chain_data_dir.as_ref(), true)?.write(path)?;
+    }
+    Ok(plan)
+}
 
-    // construct new meta data
-    let mut meta_config_toml = File::create(new_chain_metadata_dir.join("config.toml"))
-        .context("cannot create meta `config.toml`")?;
-    let meta_config_content = toml::to_string_pretty(&meta_config).unwrap();
-    meta_config_toml
-        .write_all(meta_config_content.as_bytes())
-        .context("cannot write meta `config.toml`")?;
+// Run the whole migration pipeline in memory without writing anything.
+pub fn plan<P, Q>(
+    chain_data_dir: P,
+    new_chain_data_dir: Q,
+    chain_name: &str,
+    opts: &MigrateOpts,
+    keys: &mut KeyMaterial,
+) -> Result<MigrationPlan>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let chain_data_dir = chain_data_dir.as_ref();
+    let new_chain_data_dir = new_chain_data_dir.as_ref();
 
-    let sample_node = node_dirs.first().unwrap();
-    migrate_log4rs_and_kms_db(sample_node, new_chain_metadata_dir)
-        .context("cannot copy log4rs and kms_db config to meta config dir")?;
+    let ChainConfigs {
+        node_dirs,
+        skipped_dirs,
+        node_configs,
+        meta_config,
+        membership,
+        source,
+        migration_path,
+    } = migrate_configs(chain_data_dir, chain_name, opts, keys)?;
+
+    let sample_node = node_dirs
+        .first()
+        .context("Empty chain. No node dir found")?;
+    let meta = plan_meta(sample_node, new_chain_data_dir, chain_name, meta_config)?;
 
     // construct new node data
-    for (old_node_dir, node_config) in node_dirs.iter().zip(node_configs) {
-        let new_node_dir = new_chain_data_dir.join(format!(
-            "{}-{}",
-            chain_name,
-            node_config
-                .controller
-                .node_address
-                .strip_prefix("0x")
-                .context("invalid node address, must be a hex string with `0x` prefix")?
-        ));
-        fs::create_dir_all(&new_node_dir).with_context(|| {
-            format!(
-                "cannot create new node dir `{}`",
-                new_node_dir.to_string_lossy()
+    let nodes = node_dirs
+        .into_iter()
+        .zip(node_configs)
+        .map(|(old_node_dir, node_config)| {
+            plan_node(
+                old_node_dir,
+                node_config,
+                new_chain_data_dir,
+                chain_name,
+                opts.transfer,
             )
-        })?;
+        })
+        .collect::<Result<Vec<NodePlan>>>()?;
+
+    Ok(MigrationPlan {
+        chain_name: chain_name.to_string(),
+        chain_dir: chain_data_dir.to_path_buf(),
+        out_dir: new_chain_data_dir.to_path_buf(),
+        source,
+        migration_path,
+        meta,
+        nodes,
+        skipped_dirs,
+        membership,
+        conflicts: vec![],
+        on_conflict: opts.on_conflict,
+        free_space: free_space(new_chain_data_dir)?,
+    })
+}
 
-        let mut node_config_toml = File::create(new_node_dir.join("config.toml"))
-            .context("cannot create node's `config.toml`")?;
-        let node_config_content = toml::to_string_pretty(&node_config).unwrap();
-        node_config_toml
-            .write_all(node_config_content.as_bytes())
-            .context("cannot write node's `config.toml`")?;
+// The new metadata dir gets the log4rs yamls and kms db of `sample_node`.
+pub(crate) fn plan_meta(
+    sample_node: &Path,
+    new_chain_data_dir: &Path,
+    chain_name: &str,
+    config: new::MetaConfig,
+) -> Result<MetaPlan> {
+    let new_dir = new_chain_data_dir.join(chain_name);
+    Ok(MetaPlan {
+        copies: migrate_log4rs_and_kms_db(sample_node, &new_dir)
+            .context("cannot plan copying log4rs and kms_db config to meta config dir")?,
+        new_dir,
+        config,
+    })
+}
 
+// The new node dir is named after the node address.
+pub(crate) fn new_node_dir(
+    new_chain_data_dir: &Path,
+    chain_name: &str,
+    node_address: &str,
+) -> Result<PathBuf> {
+    let addr = node_address
+        .strip_prefix("0x")
+        .context("invalid node address, must be a hex string with `0x` prefix")?;
+    Ok(new_chain_data_dir.join(format!("{}-{}", chain_name, addr)))
+}
+
+pub(crate) fn plan_node(
+    old_node_dir: PathBuf,
+    node_config: new::Config,
+    new_chain_data_dir: &Path,
+    chain_name: &str,
+    transfer: Transfer,
+) -> Result<NodePlan> {
+    let new_node_dir = new_node_dir(
+        new_chain_data_dir,
+        chain_name,
+        &node_config.controller.node_address,
+    )?;
+
+    // Fail before touching anything rather than in the middle of the migration.
+    if transfer.mode.needs_same_filesystem() && transfer.fallback == Fallback::Fail {
+        ensure!(
+            same_filesystem(&old_node_dir, &new_node_dir)?,
+            "cannot {} data from `{}` to `{}` across filesystems, \
+            use `--data-mode-fallback copy` or another data mode",
+            transfer.mode,
+            old_node_dir.to_string_lossy(),
+            new_node_dir.to_string_lossy()
+        );
+    }
+
+    let mut copies =
         migrate_log4rs_and_kms_db(&old_node_dir, &new_node_dir).with_context(|| {
             format!(
-                "cannot migrate log4rs yamls and kms db for `{}`",
+                "cannot plan migrating log4rs yamls and kms db for `{}`",
                 old_node_dir.to_string_lossy()
             )
         })?;
-        migrate_chain_data_and_storage_data_and_logs(&old_node_dir, &new_node_dir).with_context(
-            || {
+    copies.extend(
+        migrate_chain_data_and_storage_data_and_logs(&old_node_dir, &new_node_dir, transfer)
+            .with_context(|| {
                 format!(
-                    "cannot migrate {{chain data, storage data, logs}} for `{}`",
+                    "cannot plan migrating {{chain data, storage data, logs}} for `{}`",
                     old_node_dir.to_string_lossy()
                 )
-            },
-        )?;
+            })?,
+    );
+
+    let entries = inventory(&old_node_dir)?;
+
+    Ok(NodePlan {
+        old_dir: old_node_dir,
+        new_dir: new_node_dir,
+        config: node_config,
+        copies,
+        entries,
+    })
+}
+
+// `plan` is made in the staging dir of `out_dir`.
+pub fn execute(
+    plan: &MigrationPlan,
+    out_dir: &Path,
+    journal: &mut Journal,
+    opts: &MigrateOpts,
+) -> Result<()> {
+    fs::create_dir_all(&plan.out_dir).with_context(|| {
+        format!(
+            "cannot create new chain dir `{}`",
+            plan.out_dir.to_string_lossy()
+        )
+    })?;
+    execute_meta(&plan.meta, journal)?;
+
+    // construct new node data, `jobs` nodes at a time
+    let jobs = opts
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from))
+        .clamp(1, plan.nodes.len().max(1));
+    let progress = Progress::new(&plan.nodes, &plan.out_dir, out_dir, opts.progress);
+    let journal = Mutex::new(journal);
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut errors = progress.report_while(|| {
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    s.spawn(|| -> Result<()> {
+                        // Stop taking new nodes once any of them fails.
+                        while !failed.load(Ordering::Relaxed) {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let node = match plan.nodes.get(i) {
+                                Some(node) => node,
+                                None => break,
+                            };
+                            if let Err(e) = execute_node(node, &journal, progress.node(i)) {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .filter_map(|w| w.join().unwrap().err())
+                .collect::<Vec<_>>()
+        })
+    });
+
+    // Report all failures, return the first one.
+    if !errors.is_empty() {
+        for e in &errors[1..] {
+            eprintln!("error: {:#}", e);
+        }
+        return Err(errors.remove(0));
+    }
+
+    write_chain_manifest(
+        &plan.chain_name,
+        &plan.out_dir,
+        &plan.meta,
+        &plan.nodes,
+        journal.into_inner().unwrap(),
+    )
+}
+
+// Write the meta config and copy the log4rs yamls and kms db into the new metadata dir.
+pub(crate) fn execute_meta(meta: &MetaPlan, journal: &mut Journal) -> Result<()> {
+    fs::create_dir_all(&meta.new_dir).with_context(|| {
+        format!(
+            "cannot create new meta dir `{}`",
+            meta.new_dir.to_string_lossy()
+        )
+    })?;
+
+    // construct new meta data
+    let meta_config_path = meta.new_dir.join("config.toml");
+    journal.step(&write_step(&meta_config_path), |_| {
+        let mut meta_config_toml =
+            File::create(&meta_config_path).context("cannot create meta `config.toml`")?;
+        let meta_config_content =
+            toml::to_string_pretty(&meta.config).context("cannot serialize meta `config.toml`")?;
+        meta_config_toml
+            .write_all(meta_config_content.as_bytes())
+            .context("cannot write meta `config.toml`")
+    })?;
+
+    // On rerun, copy again whatever has been changed since the last run.
+    let chain_manifest_path = meta.new_dir.join(MANIFEST_FILE);
+    if chain_manifest_path.exists() {
+        let chain_manifest: ChainDataManifest = manifest::load(&chain_manifest_path)?;
+        redo_changed_copies(journal, &meta.new_dir, &chain_manifest.files, &meta.copies)?;
     }
 
+    // Meta copies are a few small files, not worth reporting.
+    let meta_progress = NodeProgress::untracked();
+    for op in &meta.copies {
+        journal
+            .step(&copy_step(op), |interrupted| {
+                op.execute(interrupted, &meta_progress.op(op))
+            })
+            .context("cannot copy log4rs and kms_db config to meta config dir")?;
+    }
     Ok(())
 }
 
-fn migrate_log4rs_and_kms_db<P, Q>(old_dir: P, new_dir: Q) -> Result<()>
-where
-    P: AsRef<Path>,
-    Q: AsRef<Path>,
-{
-    let old_dir = old_dir.as_ref();
-    let new_dir = new_dir.as_ref();
+// Record the metadata files and the digests of the manifests of `nodes`.
+// Cheap to build, so it's rebuilt every run rather than journaled.
+pub(crate) fn write_chain_manifest(
+    chain_name: &str,
+    new_chain_data_dir: &Path,
+    meta: &MetaPlan,
+    nodes: &[NodePlan],
+    journal: &mut Journal,
+) -> Result<()> {
+    let node_dirs: Vec<(&str, &Path)> = nodes
+        .iter()
+        .map(|n| {
+            (
+                n.config.controller.node_address.as_str(),
+                n.new_dir.as_path(),
+            )
+        })
+        .collect();
+    let chain_manifest = ChainDataManifest::build(
+        chain_name,
+        new_chain_data_dir,
+        &meta.new_dir,
+        &meta.copies,
+        &node_dirs,
+    )
+    .or_else(|e| {
+        redo_mismatched_copies(journal, &meta.copies, &e)?;
+        Err(e)
+    })
+    .context("cannot build chain data manifest")?;
+    manifest::save(&chain_manifest, &meta.new_dir.join(MANIFEST_FILE))
+}
 
-    let files = [
-        "controller-log4rs.yaml",
-        "storage-log4rs.yaml",
-        "executor-log4rs.yaml",
-        "kms-log4rs.yaml",
-        "kms.db",
-    ];
+// Check the files in `dir` against those recorded in its manifest by the last run,
+// and copy again those changed. Return whether any has been changed.
+fn redo_changed_copies(
+    journal: &mut Journal,
+    dir: &Path,
+    files: &[FileEntry],
+    copies: &[CopyOp],
+) -> Result<bool> {
+    let changed = manifest::check(dir, files);
+    let redo: Vec<&CopyOp> = copies
+        .iter()
+        .filter(|op| changed.iter().any(|(p, _)| dir.join(p).starts_with(&op.to)))
+        .collect();
+    for (path, problem) in &changed {
+        eprintln!(
+            "warning: `{}` has been changed since the last run ({}), copy it again",
+            dir.join(path).to_string_lossy(),
+            problem
+        );
+    }
+    for op in &redo {
+        ensure!(
+            op.transfer.mode != DataMode::Move || op.from.exists(),
+            "cannot copy `{}` again, its source has been moved",
+            op.to.to_string_lossy()
+        );
+    }
+
+    // So that a delta copy doesn't take them as unchanged.
+    for (path, _) in &changed {
+        let path = dir.join(path);
+        if path.is_file() {
+            fs::remove_file(&path)
+                .with_context(|| format!("cannot remove `{}`", path.to_string_lossy()))?;
+        }
+    }
+    for op in redo {
+        journal.redo(&copy_step(op))?;
+    }
+    Ok(!changed.is_empty())
+}
+
+pub(crate) fn execute_node(
+    node: &NodePlan,
+    journal: &SharedJournal,
+    progress: &NodeProgress,
+) -> Result<()> {
+    fs::create_dir_all(&node.new_dir).with_context(|| {
+        format!(
+            "cannot create new node dir `{}`",
+            node.new_dir.to_string_lossy()
+        )
+    })?;
+
+    let node_config_path = node.new_dir.join("config.toml");
+    shared_step(journal, &write_step(&node_config_path), |_| {
+        let mut node_config_toml =
+            File::create(&node_config_path).context("cannot create node's `config.toml`")?;
+        let node_config_content = toml::to_string_pretty(&node.config)
+            .context("cannot serialize node's `config.toml`")?;
+        node_config_toml
+            .write_all(node_config_content.as_bytes())
+            .context("cannot write node's `config.toml`")
+    })?;
+
+    // On rerun, copy again whatever has been changed since the last run.
+    let manifest_path = node.new_dir.join(MANIFEST_FILE);
+    let manifest_step = write_step(&manifest_path);
+    {
+        let mut journal = journal.lock().unwrap();
+        if journal.completed.contains(&manifest_step) {
+            let changed = match manifest::load::<DataManifest>(&manifest_path) {
+                Ok(manifest) => {
+                    redo_changed_copies(&mut journal, &node.new_dir, &manifest.files, &node.copies)?
+                }
+                Err(e) => {
+                    eprintln!("warning: {:#}, copy node data again", e);
+                    for op in &node.copies {
+                        journal.redo(&copy_step(op))?;
+                    }
+                    true
+                }
+            };
+            if changed {
+                journal.redo(&manifest_step)?;
+            }
+        }
+    }
 
-    for f in files {
-        let from = old_dir.join(f);
-        let to = new_dir.join(f);
-        fs::copy(&from, &to).with_context(|| {
+    for op in &node.copies {
+        let op_progress = progress.op(op);
+        shared_step(journal, &copy_step(op), |interrupted| {
+            op.execute(interrupted, &op_progress)
+        })
+        .with_context(|| {
             format!(
-                "cannot copy file from `{}` to `{}`",
-                from.to_string_lossy(),
-                to.to_string_lossy()
+                "cannot migrate node data for `{}`",
+                node.old_dir.to_string_lossy()
             )
         })?;
+        op_progress.finish();
+    }
+
+    shared_step(journal, &manifest_step, |_| {
+        let manifest = match DataManifest::build(
+            &node.config.controller.node_address,
+            &node.new_dir,
+            &node.copies,
+        ) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                redo_mismatched_copies(&mut journal.lock().unwrap(), &node.copies, &e)?;
+                return Err(e);
+            }
+        };
+        manifest::save(&manifest, &manifest_path)
+    })
+    .with_context(|| {
+        format!(
+            "cannot build data manifest for `{}`",
+            node.new_dir.to_string_lossy()
+        )
+    })?;
+    progress.finish();
+
+    Ok(())
+}
+
+// Copy again next run whatever doesn't match its source, rather than failing on it forever.
+// Copies that couldn't be checked are all copied again.
+fn redo_mismatched_copies(
+    journal: &mut Journal,
+    copies: &[CopyOp],
+    e: &anyhow::Error,
+) -> Result<()> {
+    let mismatch = e.downcast_ref::<CopyMismatch>();
+    for op in copies {
+        if mismatch.is_none_or(|m| m.copies.contains(&op.to)) {
+            journal.redo(&copy_step(op))?;
+        }
     }
     Ok(())
 }
 
-fn migrate_chain_data_and_storage_data_and_logs<P, Q>(old_dir: P, new_dir: Q) -> Result<()>
+fn write_step(path: &Path) -> String {
+    format!("write `{}`", path.to_string_lossy())
+}
+
+fn copy_step(op: &CopyOp) -> String {
+    format!("copy `{}`", op.to.to_string_lossy())
+}
+
+pub(crate) const LOG4RS_AND_KMS_DB_FILES: [&str; 5] = [
+    "controller-log4rs.yaml",
+    "storage-log4rs.yaml",
+    "executor-log4rs.yaml",
+    "kms-log4rs.yaml",
+    "kms.db",
+];
+
+pub(crate) const CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS: [&str; 3] =
+    ["chain_data", "data", "logs"];
+
+// Left behind in the old node dir, the new raft consensus starts afresh,
+// the old network keys are of no use to either new network, `network_tls`
+// authenticates with the generated certs and the `network_p2p` config has
+// no key to carry them, its peers are only addresses,
+// and the old `config.toml` is superseded by the generated one.
+pub(crate) const DISCARDED_FILES_AND_DIRS: [&str; 6] = [
+    "raft-data-dir",
+    "network_key",
+    "node_key",
+    "consensus-log4rs.yaml",
+    "network-log4rs.yaml",
+    "config.toml",
+];
+
+fn migrate_log4rs_and_kms_db<P, Q>(old_dir: P, new_dir: Q) -> Result<Vec<CopyOp>>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
@@ -700,23 +1417,26 @@ where
     let old_dir = old_dir.as_ref();
     let new_dir = new_dir.as_ref();
 
-    let dirs = ["chain_data", "data", "logs"];
+    LOG4RS_AND_KMS_DB_FILES
+        .iter()
+        .map(|f| CopyOp::file(old_dir.join(f), new_dir.join(f)))
+        .collect()
+}
 
-    let opts = CopyOptions {
-        skip_exist: true,
-        copy_inside: true,
-        ..Default::default()
-    };
-    for d in dirs {
-        let from = old_dir.join(d);
-        let to = new_dir.join(d);
-        copy_dir(&from, &to, &opts).with_context(|| {
-            format!(
-                "cannot copy dir from `{}` to `{}`",
-                from.to_string_lossy(),
-                to.to_string_lossy()
-            )
-        })?;
-    }
-    Ok(())
+fn migrate_chain_data_and_storage_data_and_logs<P, Q>(
+    old_dir: P,
+    new_dir: Q,
+    transfer: Transfer,
+) -> Result<Vec<CopyOp>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let old_dir = old_dir.as_ref();
+    let new_dir = new_dir.as_ref();
+
+    CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS
+        .iter()
+        .map(|d| CopyOp::dir(old_dir.join(d), new_dir.join(d), transfer))
+        .collect()
 }
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::migrate::new;
+
+// The network service of the new chain.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkKind {
+    #[default]
+    Tls,
+    P2p,
+}
+
+impl NetworkKind {
+    pub const VARIANTS: [&'static str; 2] = ["tls", "p2p"];
+
+    // The network section of a node from its network grpc port, listen port and peers' `(host, port)`.
+    // TLS certs and peer domains are left for `fill_network_tls_certs`.
+    pub fn generate(
+        self,
+        grpc_port: u16,
+        listen_port: u16,
+        peers: &[(String, u16)],
+    ) -> new::Network {
+        match self {
+            Self::Tls => new::Network::Tls(new::NetworkTlsConfig {
+                ca_cert: None,
+                cert: None,
+                grpc_port,
+                listen_port,
+                peers: peers
+                    .iter()
+                    .map(|(host, port)| new::NetworkTlsPeerConfig {
+                        domain: None,
+                        host: host.clone(),
+                        port: *port,
+                    })
+                    .collect(),
+            }),
+            Self::P2p => new::Network::P2p(new::NetworkP2pConfig {
+                grpc_port,
+                port: listen_port,
+                peers: peers
+                    .iter()
+                    .map(|(host, port)| new::NetworkP2pPeerConfig {
+                        address: multiaddr(host, *port),
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    // Only `network_tls` needs a CA and a cert per node.
+    pub fn needs_certs(self) -> bool {
+        self == Self::Tls
+    }
+}
+
+impl FromStr for NetworkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tls" => Ok(Self::Tls),
+            "p2p" => Ok(Self::P2p),
+            _ => bail!("unknown network `{}`", s),
+        }
+    }
+}
+
+impl fmt::Display for NetworkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Tls => "tls",
+            Self::P2p => "p2p",
+        };
+        f.write_str(s)
+    }
+}
+
+impl new::Network {
+    pub fn kind(&self) -> NetworkKind {
+        match self {
+            Self::Tls(_) => NetworkKind::Tls,
+            Self::P2p(_) => NetworkKind::P2p,
+        }
+    }
+
+    pub fn grpc_port(&self) -> u16 {
+        match self {
+            Self::Tls(c) => c.grpc_port,
+            Self::P2p(c) => c.grpc_port,
+        }
+    }
+
+    // Where the node listens for its peers.
+    pub fn listen_port(&self) -> u16 {
+        match self {
+            Self::Tls(c) => c.listen_port,
+            Self::P2p(c) => c.port,
+        }
+    }
+
+    // Peers' `(host, port)`, whatever the service.
+    pub fn peer_addrs(&self) -> Result<Vec<(String, u16)>> {
+        match self {
+            Self::Tls(c) => Ok(c.peers.iter().map(|p| (p.host.clone(), p.port)).collect()),
+            Self::P2p(c) => c
+                .peers
+                .iter()
+                .map(|p| parse_multiaddr(&p.address))
+                .collect(),
+        }
+    }
+}
+
+impl new::MetaNetwork {
+    pub fn kind(&self) -> NetworkKind {
+        match self {
+            Self::Tls(_) => NetworkKind::Tls,
+            Self::P2p(_) => NetworkKind::P2p,
+        }
+    }
+}
+
+// e.g. `/ip4/127.0.0.1/tcp/40000`, or `/dns4/node0/tcp/40000` for a host name.
+pub fn multiaddr(host: &str, port: u16) -> String {
+    let protocol = if host.parse::<Ipv4Addr>().is_ok() {
+        "ip4"
+    } else if host.parse::<Ipv6Addr>().is_ok() {
+        "ip6"
+    } else {
+        "dns4"
+    };
+    format!("/{}/{}/tcp/{}", protocol, host, port)
+}
+
+// The inverse of `multiaddr`.
+fn parse_multiaddr(addr: &str) -> Result<(String, u16)> {
+    match addr.split('/').collect::<Vec<_>>()[..] {
+        ["", "ip4" | "ip6" | "dns" | "dns4" | "dns6", host, "tcp", port] => {
+            let port = port
+                .parse()
+                .with_context(|| format!("invalid port in multiaddr `{}`", addr))?;
+            Ok((host.to_string(), port))
+        }
+        _ => bail!(
+            "unsupported multiaddr `{}`, expect e.g. `/ip4/127.0.0.1/tcp/40000`",
+            addr
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiaddr_by_host_kind() {
+        assert_eq!(multiaddr("127.0.0.1", 40000), "/ip4/127.0.0.1/tcp/40000");
+        assert_eq!(multiaddr("::1", 40000), "/ip6/::1/tcp/40000");
+        assert_eq!(multiaddr("node0", 40001), "/dns4/node0/tcp/40001");
+        assert_eq!(
+            multiaddr("node0.example.com", 40001),
+            "/dns4/node0.example.com/tcp/40001"
+        );
+    }
+
+    #[test]
+    fn parse_multiaddr_inverts_multiaddr() {
+        for (host, port) in [("127.0.0.1", 40000), ("::1", 1), ("node0", 65535)] {
+            assert_eq!(
+                parse_multiaddr(&multiaddr(host, port)).unwrap(),
+                (host.to_string(), port)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_multiaddr_takes_any_dns() {
+        for protocol in ["dns", "dns4", "dns6"] {
+            let addr = format!("/{}/node0/tcp/40000", protocol);
+            assert_eq!(
+                parse_multiaddr(&addr).unwrap(),
+                ("node0".to_string(), 40000)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_multiaddr_rejects_malformed() {
+        for addr in [
+            "",
+            "/",
+            "ip4/127.0.0.1/tcp/40000",
+            "/ip4/127.0.0.1/udp/40000",
+            "/ip4/127.0.0.1/tcp",
+            "/ip4/127.0.0.1/tcp/40000/p2p/QmNode",
+            "/unix/tmp/tcp/40000",
+            "127.0.0.1:40000",
+        ] {
+            let e = parse_multiaddr(addr).unwrap_err();
+            assert!(
+                e.to_string().starts_with("unsupported multiaddr"),
+                "{}",
+                addr
+            );
+        }
+        for addr in [
+            "/ip4/127.0.0.1/tcp/port",
+            "/ip4/127.0.0.1/tcp/65536",
+            "/ip4/127.0.0.1/tcp/-1",
+            "/ip4/127.0.0.1/tcp/",
+        ] {
+            let e = parse_multiaddr(addr).unwrap_err();
+            assert!(e.to_string().starts_with("invalid port"), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn network_kind_round_trips() {
+        for s in NetworkKind::VARIANTS {
+            assert_eq!(s.parse::<NetworkKind>().unwrap().to_string(), s);
+        }
+        assert!("TLS".parse::<NetworkKind>().is_err());
+        assert_eq!(NetworkKind::default(), NetworkKind::Tls);
+    }
+
+    #[test]
+    fn generated_peers_are_read_back() {
+        let peers = vec![
+            ("127.0.0.1".to_string(), 40001),
+            ("node2".to_string(), 40002),
+        ];
+        for kind in [NetworkKind::Tls, NetworkKind::P2p] {
+            let network = kind.generate(50000, 40000, &peers);
+            assert_eq!(network.kind(), kind);
+            assert_eq!(network.grpc_port(), 50000);
+            assert_eq!(network.listen_port(), 40000);
+            assert_eq!(network.peer_addrs().unwrap(), peers);
+        }
+    }
+}
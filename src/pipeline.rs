@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::cert::KeyMaterial;
+use crate::migrate::new;
+use crate::migrate::MigrateOpts;
+use crate::migrate::NodeConfigMigrate;
+use crate::v6_1_0_to_v6_3_0;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const V6_1_0: Version = Version::new(6, 1, 0);
+    pub const V6_3_0: Version = Version::new(6, 3, 0);
+
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+// The configs of a whole chain in memory, in the shape of its version.
+// Steps convert between these, only loading and writing touch the files.
+#[allow(clippy::large_enum_variant)]
+pub enum ChainModel {
+    V6_1_0(OldChain),
+    V6_3_0(NewChain),
+}
+
+impl ChainModel {
+    pub fn version(&self) -> Version {
+        match self {
+            Self::V6_1_0(_) => Version::V6_1_0,
+            Self::V6_3_0(_) => Version::V6_3_0,
+        }
+    }
+}
+
+// A 6.1.0 chain, every node has its own per service config files.
+pub struct OldChain {
+    // Sorted by node id, the configs of `nodes` are in the same order.
+    pub node_dirs: Vec<PathBuf>,
+    pub nodes: Vec<NodeConfigMigrate>,
+    // Admin address -> `key_id` of the admin account, for every admin of the nodes
+    // that has one in the metadata dir. Nodes may disagree on the admin.
+    pub admin_key_ids: HashMap<String, u64>,
+}
+
+// A 6.3.0 chain, a single `config.toml` per node plus the one in the metadata dir.
+pub struct NewChain {
+    pub node_configs: Vec<new::Config>,
+    pub meta_config: new::MetaConfig,
+}
+
+// What a step may need besides the chain itself.
+pub struct StepContext<'a> {
+    pub opts: &'a MigrateOpts,
+    // Certs and keys to reuse, new ones are put into it.
+    pub keys: &'a mut KeyMaterial,
+}
+
+// One hop between two releases. Supporting a new release is adding a module
+// implementing this and registering it in `Registry::builtin`.
+pub trait Migration {
+    fn source(&self) -> Version;
+    fn target(&self) -> Version;
+    fn migrate(&self, chain: ChainModel, ctx: &mut StepContext) -> Result<ChainModel>;
+}
+
+pub struct Registry {
+    steps: Vec<Box<dyn Migration>>,
+}
+
+impl Registry {
+    pub fn builtin() -> Self {
+        let mut registry = Self { steps: vec![] };
+        registry.register(Box::new(v6_1_0_to_v6_3_0::Step));
+        registry
+    }
+
+    pub fn register(&mut self, step: Box<dyn Migration>) {
+        self.steps.push(step);
+    }
+
+    // The shortest chain of steps from `source` to `target`.
+    pub fn plan(&self, source: Version, target: Version) -> Result<Vec<&dyn Migration>> {
+        ensure!(source != target, "the chain is already at {}", target);
+
+        // Breadth first, remembering the step that first reached each version.
+        let mut reached_by: HashMap<Version, usize> = HashMap::new();
+        let mut queue = VecDeque::from([source]);
+        while let Some(v) = queue.pop_front() {
+            if v == target {
+                break;
+            }
+            for (i, step) in self.steps.iter().enumerate() {
+                let next = step.target();
+                if step.source() == v && next != source && !reached_by.contains_key(&next) {
+                    reached_by.insert(next, i);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        let mut steps = vec![];
+        let mut v = target;
+        while v != source {
+            let i = *reached_by
+                .get(&v)
+                .with_context(|| format!("no migration path from {} to {}", source, target))?;
+            steps.push(self.steps[i].as_ref());
+            v = self.steps[i].source();
+        }
+        steps.reverse();
+        Ok(steps)
+    }
+}
+
+// Run `steps` one after another, each on what the previous one produced.
+pub fn run(
+    steps: &[&dyn Migration],
+    chain: ChainModel,
+    ctx: &mut StepContext,
+) -> Result<ChainModel> {
+    let mut chain = chain;
+    for step in steps {
+        if chain.version() != step.source() {
+            bail!(
+                "step {} -> {} cannot migrate a {} chain",
+                step.source(),
+                step.target(),
+                chain.version()
+            );
+        }
+        chain = step.migrate(chain, ctx).with_context(|| {
+            format!("cannot migrate from {} to {}", step.source(), step.target())
+        })?;
+        ensure!(
+            chain.version() == step.target(),
+            "step {} -> {} produced a {} chain",
+            step.source(),
+            step.target(),
+            chain.version()
+        );
+    }
+    Ok(chain)
+}
+
+// How steps are shown to users, e.g. `6.1.0 -> 6.3.0`.
+pub fn describe(steps: &[&dyn Migration]) -> String {
+    match steps.first() {
+        Some(first) => std::iter::once(first.source())
+            .chain(steps.iter().map(|s| s.target()))
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" -> "),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A step that's only planned, never run.
+    struct Hop(Version, Version);
+
+    impl Migration for Hop {
+        fn source(&self) -> Version {
+            self.0
+        }
+
+        fn target(&self) -> Version {
+            self.1
+        }
+
+        fn migrate(&self, _chain: ChainModel, _ctx: &mut StepContext) -> Result<ChainModel> {
+            unreachable!()
+        }
+    }
+
+    fn v(minor: u32) -> Version {
+        Version::new(6, minor, 0)
+    }
+
+    fn registry(hops: &[(u32, u32)]) -> Registry {
+        let mut registry = Registry { steps: vec![] };
+        for &(from, to) in hops {
+            registry.register(Box::new(Hop(v(from), v(to))));
+        }
+        registry
+    }
+
+    fn path(registry: &Registry, from: u32, to: u32) -> Result<String> {
+        Ok(describe(&registry.plan(v(from), v(to))?))
+    }
+
+    #[test]
+    fn builtin_migrates_6_1_0_to_6_3_0() {
+        let registry = Registry::builtin();
+        let steps = registry.plan(Version::V6_1_0, Version::V6_3_0).unwrap();
+        assert_eq!(describe(&steps), "6.1.0 -> 6.3.0");
+    }
+
+    #[test]
+    fn plan_chains_steps() {
+        let registry = registry(&[(2, 3), (1, 2), (3, 4)]);
+        assert_eq!(
+            path(&registry, 1, 4).unwrap(),
+            "6.1.0 -> 6.2.0 -> 6.3.0 -> 6.4.0"
+        );
+        assert_eq!(path(&registry, 2, 3).unwrap(), "6.2.0 -> 6.3.0");
+    }
+
+    #[test]
+    fn plan_takes_the_shortest_path() {
+        let registry = registry(&[(1, 2), (2, 3), (3, 4), (1, 3)]);
+        assert_eq!(path(&registry, 1, 4).unwrap(), "6.1.0 -> 6.3.0 -> 6.4.0");
+    }
+
+    #[test]
+    fn plan_survives_cycles() {
+        let registry = registry(&[(1, 2), (2, 1), (2, 3), (3, 2)]);
+        assert_eq!(path(&registry, 1, 3).unwrap(), "6.1.0 -> 6.2.0 -> 6.3.0");
+        assert_eq!(path(&registry, 3, 1).unwrap(), "6.3.0 -> 6.2.0 -> 6.1.0");
+    }
+
+    #[test]
+    fn plan_fails_without_a_path() {
+        let registry = registry(&[(1, 2), (3, 4)]);
+        let e = path(&registry, 1, 4).unwrap_err();
+        assert_eq!(e.to_string(), "no migration path from 6.1.0 to 6.4.0");
+        // Steps only go forward.
+        assert!(path(&registry, 2, 1).is_err());
+        assert!(path(&Registry { steps: vec![] }, 1, 2).is_err());
+    }
+
+    #[test]
+    fn plan_fails_at_the_target() {
+        let e = path(&registry(&[(1, 2)]), 2, 2).unwrap_err();
+        assert_eq!(e.to_string(), "the chain is already at 6.2.0");
+    }
+
+    #[test]
+    fn describe_no_steps() {
+        assert_eq!(describe(&[]), "");
+    }
+}
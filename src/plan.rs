@@ -0,0 +1,473 @@
+use std::fmt;
+use std::fs;
+use std::iter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use fs_extra::dir::copy_with_progress as copy_dir_with_progress;
+use fs_extra::dir::get_dir_content;
+use fs_extra::dir::CopyOptions;
+use fs_extra::dir::TransitProcessResult;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::consistency::Membership;
+use crate::detect::Detected;
+use crate::inventory::names;
+use crate::inventory::Entry;
+use crate::inventory::Fate;
+use crate::migrate::new;
+use crate::progress::OpProgress;
+use crate::staging;
+use crate::staging::OnConflict;
+use crate::transfer::hardlink_dir;
+use crate::transfer::move_dir;
+use crate::transfer::reflink_dir;
+use crate::transfer::same_filesystem;
+use crate::transfer::sync_dir;
+use crate::transfer::DataMode;
+use crate::transfer::Fallback;
+use crate::transfer::Transfer;
+
+// Everything `migrate` is going to do, computed without touching the output dir.
+pub struct MigrationPlan {
+    pub chain_name: String,
+    pub chain_dir: PathBuf,
+    pub out_dir: PathBuf,
+    // The version the old chain is detected at.
+    pub source: Detected,
+    // The versions the chain goes through, e.g. `6.1.0 -> 6.3.0`.
+    pub migration_path: String,
+
+    pub meta: MetaPlan,
+    pub nodes: Vec<NodePlan>,
+    // Dirs that look like node dirs but are skipped, with the reason.
+    pub skipped_dirs: Vec<(PathBuf, String)>,
+    pub membership: Membership,
+
+    // Meta and node dirs already in the final out dir, and what to do with them.
+    pub conflicts: Vec<PathBuf>,
+    pub on_conflict: OnConflict,
+    // Free space on the out dir's filesystem, `None` if unknown.
+    pub free_space: Option<u64>,
+}
+
+pub struct MetaPlan {
+    pub new_dir: PathBuf,
+    pub config: new::MetaConfig,
+    pub copies: Vec<CopyOp>,
+}
+
+pub struct NodePlan {
+    pub old_dir: PathBuf,
+    pub new_dir: PathBuf,
+    pub config: new::Config,
+    pub copies: Vec<CopyOp>,
+    // Everything in the old node dir and what becomes of it.
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CopyKind {
+    File,
+    Dir,
+}
+
+pub struct CopyOp {
+    pub kind: CopyKind,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    // Bytes to be copied, for dirs it's the total size of its content.
+    pub size: u64,
+    // Number of files to be copied.
+    pub files: u64,
+    // Free space taken on the out dir's filesystem, short of what's already there.
+    pub space: u64,
+    // How dirs are transferred, files are always copied.
+    pub transfer: Transfer,
+}
+
+impl CopyOp {
+    pub fn file(from: PathBuf, to: PathBuf) -> Result<Self> {
+        let size = fs::metadata(&from)
+            .with_context(|| format!("cannot stat file `{}`", from.to_string_lossy()))?
+            .len();
+        let space = size.saturating_sub(fs::metadata(&to).map_or(0, |m| m.len()));
+        Ok(Self {
+            kind: CopyKind::File,
+            from,
+            to,
+            size,
+            files: 1,
+            space,
+            transfer: Transfer::default(),
+        })
+    }
+
+    pub fn dir(from: PathBuf, to: PathBuf, transfer: Transfer) -> Result<Self> {
+        // The source of a completed move is gone, measure what's been moved.
+        let measured = if transfer.mode == DataMode::Move && !from.exists() && to.exists() {
+            &to
+        } else {
+            &from
+        };
+        let content = get_dir_content(measured)
+            .with_context(|| format!("cannot get size of dir `{}`", measured.to_string_lossy()))?;
+        // Links and renames take no space, but a reflink may fall back to copy.
+        let takes_no_space = match transfer.mode {
+            DataMode::Hardlink | DataMode::Move => true,
+            DataMode::Reflink => transfer.fallback == Fallback::Fail,
+            DataMode::Copy | DataMode::Delta => false,
+        } && same_filesystem(measured, &to)?;
+        let space = if takes_no_space {
+            0
+        } else {
+            // What's there is either reused by a delta sync or removed before copying.
+            let existing = if to.is_dir() {
+                get_dir_content(&to).map_or(0, |c| c.dir_size)
+            } else {
+                0
+            };
+            content.dir_size.saturating_sub(existing)
+        };
+        Ok(Self {
+            kind: CopyKind::Dir,
+            from,
+            to,
+            size: content.dir_size,
+            files: content.files.len() as u64,
+            space,
+            transfer,
+        })
+    }
+
+    // Remove what's left by an interrupted copy first if `clean` is set.
+    pub fn execute(&self, clean: bool, progress: &OpProgress) -> Result<()> {
+        // A completed move leaves nothing at the source, nothing to clean or redo.
+        if clean && self.transfer.mode == DataMode::Move && !self.from.exists() && self.to.exists()
+        {
+            return Ok(());
+        }
+        // A delta sync picks up whatever is there.
+        if clean && self.to.exists() && self.transfer.mode != DataMode::Delta {
+            self.remove_target().with_context(|| {
+                format!(
+                    "cannot clean up interrupted copy `{}`",
+                    self.to.to_string_lossy()
+                )
+            })?;
+        }
+
+        match self.kind {
+            CopyKind::File => {
+                fs::copy(&self.from, &self.to).with_context(|| {
+                    format!(
+                        "cannot copy file from `{}` to `{}`",
+                        self.from.to_string_lossy(),
+                        self.to.to_string_lossy()
+                    )
+                })?;
+            }
+            CopyKind::Dir => self.transfer_dir(progress)?,
+        }
+        Ok(())
+    }
+
+    fn remove_target(&self) -> std::io::Result<()> {
+        match self.kind {
+            CopyKind::File => fs::remove_file(&self.to),
+            CopyKind::Dir => fs::remove_dir_all(&self.to),
+        }
+    }
+
+    fn transfer_dir(&self, progress: &OpProgress) -> Result<()> {
+        let Transfer { mode, fallback, .. } = self.transfer;
+        if mode == DataMode::Delta {
+            sync_dir(&self.from, &self.to, self.transfer.checksum, &|bytes| {
+                progress.add(bytes, 1)
+            })?;
+            return Ok(());
+        }
+        if mode.needs_same_filesystem() {
+            let res = if same_filesystem(&self.from, &self.to)? {
+                match mode {
+                    DataMode::Copy | DataMode::Delta => unreachable!(),
+                    DataMode::Hardlink => hardlink_dir(&self.from, &self.to),
+                    DataMode::Reflink => reflink_dir(&self.from, &self.to),
+                    DataMode::Move => move_dir(&self.from, &self.to),
+                }
+            } else {
+                Err(anyhow!(
+                    "cannot {} dir from `{}` to `{}` across filesystems",
+                    mode,
+                    self.from.to_string_lossy(),
+                    self.to.to_string_lossy()
+                ))
+            };
+            match res {
+                Ok(()) => return Ok(()),
+                Err(e) if fallback == Fallback::Copy => {
+                    eprintln!("warning: {:#}, fall back to copy", e);
+                    if self.to.exists() {
+                        self.remove_target().with_context(|| {
+                            format!(
+                                "cannot clean up failed {} `{}`",
+                                mode,
+                                self.to.to_string_lossy()
+                            )
+                        })?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Never keep what's already there, it can only be stale,
+        // and an existing `to` would get the copy nested inside it.
+        if self.to.exists() {
+            self.remove_target()
+                .with_context(|| format!("cannot remove stale `{}`", self.to.to_string_lossy()))?;
+        }
+        let opts = CopyOptions {
+            overwrite: true,
+            copy_inside: true,
+            ..Default::default()
+        };
+        let mut reported_bytes = 0;
+        let handler = |info: fs_extra::dir::TransitProcess| {
+            let finished_file =
+                info.file_total_bytes > 0 && info.file_bytes_copied == info.file_total_bytes;
+            progress.add(
+                info.copied_bytes - reported_bytes,
+                if finished_file { 1 } else { 0 },
+            );
+            reported_bytes = info.copied_bytes;
+            TransitProcessResult::ContinueOrAbort
+        };
+        copy_dir_with_progress(&self.from, &self.to, &opts, handler).with_context(|| {
+            format!(
+                "cannot copy dir from `{}` to `{}`",
+                self.from.to_string_lossy(),
+                self.to.to_string_lossy()
+            )
+        })?;
+        Ok(())
+    }
+}
+
+impl MigrationPlan {
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings: Vec<String> = self
+            .skipped_dirs
+            .iter()
+            .map(|(d, reason)| format!("skip dir `{}`: {}", d.to_string_lossy(), reason))
+            .collect();
+        warnings.extend(self.membership.warnings());
+        warnings.extend(self.nodes.iter().flat_map(NodePlan::entry_warnings));
+        warnings.extend(self.conflicts.iter().map(|d| {
+            let action = match self.on_conflict {
+                OnConflict::Fail => "the migration will fail",
+                OnConflict::Overwrite => "will be overwritten",
+                OnConflict::Skip => "will be kept as is",
+            };
+            format!("`{}` already exists, {}", d.to_string_lossy(), action)
+        }));
+        if let Err(e) = self.ensure_enough_space() {
+            warnings.push(format!("{:#}", e));
+        }
+        warnings
+    }
+
+    pub fn ensure_enough_space(&self) -> Result<()> {
+        ensure_enough_space(self.space_needed(), self.free_space, &self.out_dir)
+    }
+
+    // Find out the meta and node dirs already in `out_dir`, and leave out those nodes if skipping.
+    pub fn resolve_conflicts(&mut self, out_dir: &Path) {
+        let dirs = iter::once(&self.meta.new_dir).chain(self.nodes.iter().map(|n| &n.new_dir));
+        self.conflicts = staging::conflicts(out_dir, dirs.map(PathBuf::as_path));
+        if self.on_conflict == OnConflict::Skip {
+            let conflicts = &self.conflicts;
+            self.nodes.retain(|n| {
+                !conflicts
+                    .iter()
+                    .any(|d| d.file_name() == n.new_dir.file_name())
+            });
+        }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.meta
+            .copies
+            .iter()
+            .chain(self.nodes.iter().flat_map(|n| &n.copies))
+            .map(|op| op.size)
+            .sum()
+    }
+
+    pub fn space_needed(&self) -> u64 {
+        self.meta
+            .copies
+            .iter()
+            .chain(self.nodes.iter().flat_map(|n| &n.copies))
+            .map(|op| op.space)
+            .sum()
+    }
+}
+
+impl NodePlan {
+    pub fn entry_warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        let discarded = names(&self.entries, Fate::Discarded);
+        if !discarded.is_empty() {
+            warnings.push(format!(
+                "`{}`: {} not needed by the new chain, will not be migrated",
+                self.old_dir.to_string_lossy(),
+                discarded.join(", ")
+            ));
+        }
+        let unknown = names(&self.entries, Fate::Unknown);
+        if !unknown.is_empty() {
+            warnings.push(format!(
+                "`{}`: UNKNOWN {} will NOT be migrated, copy them by hand if they are still needed",
+                self.old_dir.to_string_lossy(),
+                unknown.join(", ")
+            ));
+        }
+        warnings
+    }
+}
+
+// Fail up front rather than running out of space in the middle of copying.
+pub fn ensure_enough_space(needed: u64, free: Option<u64>, out_dir: &Path) -> Result<()> {
+    if let Some(free) = free {
+        ensure!(
+            needed <= free,
+            "not enough space for `{}`: {} needed, {} free, {} short",
+            out_dir.to_string_lossy(),
+            human_size(needed),
+            human_size(free),
+            human_size(needed - free)
+        );
+    }
+    Ok(())
+}
+
+impl fmt::Display for MigrationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Migration plan for chain `{}` (dry run, nothing will be written)",
+            self.chain_name
+        )?;
+        writeln!(f, "  old chain dir: `{}`", self.chain_dir.to_string_lossy())?;
+        writeln!(f, "  new chain dir: `{}`", self.out_dir.to_string_lossy())?;
+        writeln!(f, "  detected version: {}", self.source)?;
+        writeln!(f, "  migration path: {}", self.migration_path)?;
+        writeln!(f, "  {} node(s) discovered:", self.nodes.len())?;
+        for n in &self.nodes {
+            writeln!(f, "    `{}`", n.old_dir.to_string_lossy())?;
+        }
+        writeln!(f, "  {} in total to copy", human_size(self.total_size()))?;
+        match self.free_space {
+            Some(free) => writeln!(
+                f,
+                "  {} of free space needed, {} available",
+                human_size(self.space_needed()),
+                human_size(free)
+            )?,
+            None => writeln!(
+                f,
+                "  {} of free space needed",
+                human_size(self.space_needed())
+            )?,
+        }
+        writeln!(f, "  {} validator(s):", self.membership.validators.len())?;
+        for v in &self.membership.validators {
+            writeln!(f, "    {}", v)?;
+        }
+        for w in self.warnings() {
+            writeln!(f, "  warning: {}", w)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "[meta] `{}`", self.meta.new_dir.to_string_lossy())?;
+        write_copies(f, &self.meta.copies)?;
+        write_toml(f, "config.toml", &self.meta.config)?;
+
+        for (i, n) in self.nodes.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "[node {}] `{}` -> `{}`",
+                i,
+                n.old_dir.to_string_lossy(),
+                n.new_dir.to_string_lossy()
+            )?;
+            writeln!(f, "  node address: {}", n.config.controller.node_address)?;
+            match (&n.config.network_host, n.config.network_port) {
+                (Some(host), Some(port)) => writeln!(f, "  self host: {}:{}", host, port)?,
+                _ => writeln!(f, "  self host: unknown")?,
+            }
+            for fate in Fate::ALL {
+                let names = names(&n.entries, fate);
+                if !names.is_empty() {
+                    writeln!(f, "  {}: {}", fate, names.join(", "))?;
+                }
+            }
+            write_copies(f, &n.copies)?;
+            write_toml(f, "config.toml", &n.config)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_copies(f: &mut fmt::Formatter<'_>, copies: &[CopyOp]) -> fmt::Result {
+    for op in copies {
+        let kind = match op.kind {
+            CopyKind::File => "file",
+            CopyKind::Dir => "dir ",
+        };
+        writeln!(
+            f,
+            "  {} {} `{}` -> `{}` ({})",
+            op.transfer.mode,
+            kind,
+            op.from.to_string_lossy(),
+            op.to.to_string_lossy(),
+            human_size(op.size)
+        )?;
+    }
+    Ok(())
+}
+
+fn write_toml<T: serde::Serialize>(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    value: &T,
+) -> fmt::Result {
+    let content = toml::to_string_pretty(value).map_err(|_| fmt::Error)?;
+    writeln!(f, "  write `{}`:", name)?;
+    for line in content.lines() {
+        writeln!(f, "    {}", line)?;
+    }
+    Ok(())
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::detect::ensure_v6_1_0;
+use crate::migrate::load_node_dirs;
+use crate::migrate::new_node_dir;
+use crate::migrate::NodeConfigMigrate;
+use crate::migrate::CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS;
+use crate::plan::ensure_enough_space;
+use crate::plan::human_size;
+use crate::plan::CopyOp;
+use crate::staging;
+use crate::transfer::free_space;
+use crate::transfer::sync_dir;
+use crate::transfer::DataMode;
+use crate::transfer::SyncStats;
+use crate::transfer::Transfer;
+
+// Copy node data to where `migrate` puts it, while the old chain is still running.
+// Rerun it to catch up, then stop the old chain and `migrate --data-mode delta`
+// to copy only what has changed since.
+pub fn presync<P, Q>(
+    chain_data_dir: P,
+    new_chain_data_dir: Q,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+    checksum: bool,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let chain_data_dir = chain_data_dir.as_ref();
+    // Presync into where `migrate` stages its output, it's taken over from there.
+    let staging_dir = staging::staging_dir(new_chain_data_dir.as_ref())?;
+
+    ensure_v6_1_0(chain_data_dir, chain_name, explicit_node_dirs)?;
+    let node_dirs = load_node_dirs(chain_data_dir, chain_name, explicit_node_dirs)?;
+    for (d, reason) in &node_dirs.skipped {
+        eprintln!("warning: skip dir `{}`: {}", d.to_string_lossy(), reason);
+    }
+
+    let mut nodes = vec![];
+    for old_node_dir in &node_dirs.dirs {
+        let node = NodeConfigMigrate::extract_from(old_node_dir).with_context(|| {
+            format!(
+                "cannot extract info from old node config in `{}`",
+                old_node_dir.to_string_lossy()
+            )
+        })?;
+        nodes.push((
+            old_node_dir,
+            new_node_dir(&staging_dir, chain_name, &node.node_addr)?,
+        ));
+    }
+
+    // Only what's new since the last presync takes more space.
+    let delta = Transfer {
+        mode: DataMode::Delta,
+        ..Default::default()
+    };
+    let mut space_needed = 0;
+    for (old_node_dir, new_node_dir) in &nodes {
+        for d in CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS {
+            space_needed += CopyOp::dir(old_node_dir.join(d), new_node_dir.join(d), delta)?.space;
+        }
+    }
+    ensure_enough_space(
+        space_needed,
+        free_space(&staging_dir)?,
+        new_chain_data_dir.as_ref(),
+    )?;
+
+    for (old_node_dir, new_node_dir) in &nodes {
+        let mut total = SyncStats::default();
+        for d in CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS {
+            let stats = sync_dir(
+                &old_node_dir.join(d),
+                &new_node_dir.join(d),
+                checksum,
+                &|_| (),
+            )
+            .with_context(|| {
+                format!(
+                    "cannot presync `{}` for `{}`",
+                    d,
+                    old_node_dir.to_string_lossy()
+                )
+            })?;
+            total.copied_files += stats.copied_files;
+            total.copied_bytes += stats.copied_bytes;
+            total.removed += stats.removed;
+        }
+        println!(
+            "presync `{}` -> `{}`: {} file(s) copied ({}), {} removed",
+            old_node_dir.to_string_lossy(),
+            new_node_dir.to_string_lossy(),
+            total.copied_files,
+            human_size(total.copied_bytes),
+            total.removed
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::cell::Cell;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::plan::human_size;
+use crate::plan::CopyOp;
+use crate::plan::NodePlan;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProgressFormat {
+    // One human readable line per node.
+    Text,
+    // One json object per line, for automation.
+    Json,
+}
+
+impl ProgressFormat {
+    pub const VARIANTS: [&'static str; 2] = ["text", "json"];
+}
+
+impl FromStr for ProgressFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("unknown progress format `{}`", s),
+        }
+    }
+}
+
+// Progress of migrating a node's data, shared with the worker migrating it.
+pub struct NodeProgress {
+    index: usize,
+    dir: String,
+    total_bytes: u64,
+    total_files: u64,
+    copied_bytes: AtomicU64,
+    copied_files: AtomicU64,
+    done: AtomicBool,
+}
+
+#[derive(Serialize, PartialEq, Eq, Clone, Copy)]
+struct Snapshot<'a> {
+    node: usize,
+    dir: &'a str,
+    copied_bytes: u64,
+    total_bytes: u64,
+    copied_files: u64,
+    total_files: u64,
+    done: bool,
+}
+
+impl NodeProgress {
+    fn new(index: usize, node: &NodePlan, staging_dir: &Path, out_dir: &Path) -> Self {
+        // Shown where the node dir ends up, not where it's staged.
+        let dir = match node.new_dir.strip_prefix(staging_dir) {
+            Ok(rel) => out_dir.join(rel),
+            Err(_) => node.new_dir.clone(),
+        };
+        Self {
+            index,
+            dir: dir.to_string_lossy().into_owned(),
+            total_bytes: node.copies.iter().map(|op| op.size).sum(),
+            total_files: node.copies.iter().map(|op| op.files).sum(),
+            copied_bytes: AtomicU64::new(0),
+            copied_files: AtomicU64::new(0),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    // For copies not worth reporting.
+    pub fn untracked() -> Self {
+        Self {
+            index: 0,
+            dir: String::new(),
+            total_bytes: 0,
+            total_files: 0,
+            copied_bytes: AtomicU64::new(0),
+            copied_files: AtomicU64::new(0),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    // Start tracking a copy op of this node.
+    pub fn op<'a>(&'a self, op: &CopyOp) -> OpProgress<'a> {
+        OpProgress {
+            node: self,
+            size: op.size,
+            files: op.files,
+            copied_bytes: Cell::new(0),
+            copied_files: Cell::new(0),
+        }
+    }
+
+    pub fn finish(&self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            node: self.index,
+            dir: &self.dir,
+            copied_bytes: self.copied_bytes.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes,
+            copied_files: self.copied_files.load(Ordering::Relaxed),
+            total_files: self.total_files,
+            done: self.done.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Progress of a single copy op. Not every data mode reports as it goes,
+// whatever is unreported is accounted when the op finishes.
+pub struct OpProgress<'a> {
+    node: &'a NodeProgress,
+    size: u64,
+    files: u64,
+    copied_bytes: Cell<u64>,
+    copied_files: Cell<u64>,
+}
+
+impl OpProgress<'_> {
+    pub fn add(&self, bytes: u64, files: u64) {
+        // Never report more than the op has, in case the source grows meanwhile.
+        let bytes = bytes.min(self.size - self.copied_bytes.get());
+        let files = files.min(self.files - self.copied_files.get());
+        self.copied_bytes.set(self.copied_bytes.get() + bytes);
+        self.copied_files.set(self.copied_files.get() + files);
+        self.node.copied_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.node.copied_files.fetch_add(files, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        self.add(
+            self.size - self.copied_bytes.get(),
+            self.files - self.copied_files.get(),
+        );
+    }
+}
+
+// Progress of migrating all nodes, reported periodically unless quiet.
+pub struct Progress {
+    nodes: Vec<NodeProgress>,
+    // `None` for quiet.
+    format: Option<ProgressFormat>,
+}
+
+impl Progress {
+    // Node dirs planned in `staging_dir` are shown under `out_dir`.
+    pub fn new(
+        nodes: &[NodePlan],
+        staging_dir: &Path,
+        out_dir: &Path,
+        format: Option<ProgressFormat>,
+    ) -> Self {
+        Self {
+            nodes: nodes
+                .iter()
+                .enumerate()
+                .map(|(i, n)| NodeProgress::new(i, n, staging_dir, out_dir))
+                .collect(),
+            format,
+        }
+    }
+
+    pub fn node(&self, index: usize) -> &NodeProgress {
+        &self.nodes[index]
+    }
+
+    // Report the progress while `f` is running, and once more when it returns.
+    pub fn report_while<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T + Send,
+        T: Send,
+    {
+        let format = match self.format {
+            Some(format) => format,
+            None => return f(),
+        };
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        thread::scope(|s| {
+            s.spawn(move || {
+                let mut last: Vec<Option<Snapshot>> = vec![None; self.nodes.len()];
+                loop {
+                    let stopped = !matches!(
+                        stop_rx.recv_timeout(REPORT_INTERVAL),
+                        Err(mpsc::RecvTimeoutError::Timeout)
+                    );
+                    // Only report nodes that have made progress.
+                    for (n, last) in self.nodes.iter().zip(&mut last) {
+                        let snapshot = n.snapshot();
+                        if last.as_ref() != Some(&snapshot) {
+                            print_snapshot(&snapshot, format);
+                            *last = Some(snapshot);
+                        }
+                    }
+                    if stopped {
+                        break;
+                    }
+                }
+            });
+            let res = f();
+            drop(stop_tx);
+            res
+        })
+    }
+}
+
+fn print_snapshot(s: &Snapshot, format: ProgressFormat) {
+    match format {
+        ProgressFormat::Text => println!(
+            "[node {}] `{}`: {} / {}, {} / {} file(s){}",
+            s.node,
+            s.dir,
+            human_size(s.copied_bytes),
+            human_size(s.total_bytes),
+            s.copied_files,
+            s.total_files,
+            if s.done { ", done" } else { "" }
+        ),
+        ProgressFormat::Json => {
+            // Serializing plain numbers and strings never fails.
+            println!("{}", serde_json::to_string(s).unwrap())
+        }
+    }
+}
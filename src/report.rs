@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use x509_parser::pem::parse_x509_pem;
+
+use crate::consistency::Membership;
+use crate::inventory::Entry;
+use crate::journal::StepTiming;
+use crate::migrate::new;
+use crate::plan::CopyKind;
+use crate::plan::CopyOp;
+use crate::plan::MigrationPlan;
+use crate::plan::NodePlan;
+
+// Machine readable summary of a migration, or of the plan for a dry run.
+#[derive(Serialize)]
+pub struct Report {
+    pub chain_name: String,
+    pub chain_dir: PathBuf,
+    pub out_dir: PathBuf,
+    pub dry_run: bool,
+    pub source_version: String,
+    pub migration_path: String,
+    // None without `network_tls`.
+    pub ca_cert: Option<CertReport>,
+    pub meta: MetaReport,
+    pub nodes: Vec<NodeReport>,
+    // Validators, missing validators and observers, also found in `warnings` as text.
+    pub membership: Membership,
+    pub warnings: Vec<String>,
+    pub steps: Vec<StepTiming>,
+    pub total_seconds: f64,
+    // Set if the migration failed.
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MetaReport {
+    pub new_dir: PathBuf,
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Serialize)]
+pub struct NodeReport {
+    pub old_dir: PathBuf,
+    pub new_dir: PathBuf,
+    pub node_address: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub cert: Option<CertReport>,
+    pub files: Vec<FileReport>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+pub struct FileReport {
+    pub kind: &'static str,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub mode: String,
+    pub size: u64,
+    pub files: u64,
+}
+
+#[derive(Serialize)]
+pub struct CertReport {
+    pub sha256_fingerprint: String,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+impl Report {
+    // Paths under `plan.out_dir` are reported under `out_dir`, where the staged output ends up.
+    pub fn new(plan: &MigrationPlan, out_dir: &Path, dry_run: bool) -> Result<Self> {
+        let rebase = |p: &Path| match p.strip_prefix(&plan.out_dir) {
+            Ok(rel) => out_dir.join(rel),
+            Err(_) => p.to_path_buf(),
+        };
+        let files = |copies: &[CopyOp]| {
+            copies
+                .iter()
+                .map(|op| FileReport {
+                    kind: match op.kind {
+                        CopyKind::File => "file",
+                        CopyKind::Dir => "dir",
+                    },
+                    from: op.from.clone(),
+                    to: rebase(&op.to),
+                    mode: op.transfer.mode.to_string(),
+                    size: op.size,
+                    files: op.files,
+                })
+                .collect()
+        };
+        let node_report = |n: &NodePlan| -> Result<NodeReport> {
+            Ok(NodeReport {
+                old_dir: n.old_dir.clone(),
+                new_dir: rebase(&n.new_dir),
+                node_address: n.config.controller.node_address.clone(),
+                host: n.config.network_host.clone(),
+                port: n.config.network_port,
+                cert: match &n.config.network {
+                    new::Network::Tls(network) => {
+                        network.cert.as_deref().map(cert_report).transpose()?
+                    }
+                    _ => None,
+                },
+                files: files(&n.copies),
+                entries: n.entries.clone(),
+            })
+        };
+
+        Ok(Self {
+            chain_name: plan.chain_name.clone(),
+            chain_dir: plan.chain_dir.clone(),
+            out_dir: out_dir.to_path_buf(),
+            dry_run,
+            source_version: plan.source.version.to_string(),
+            migration_path: plan.migration_path.clone(),
+            ca_cert: plan
+                .meta
+                .config
+                .current_config
+                .ca_cert_pem
+                .as_deref()
+                .map(cert_report)
+                .transpose()?,
+            meta: MetaReport {
+                new_dir: rebase(&plan.meta.new_dir),
+                files: files(&plan.meta.copies),
+            },
+            nodes: plan.nodes.iter().map(node_report).collect::<Result<_>>()?,
+            membership: plan.membership.clone(),
+            warnings: plan.warnings(),
+            steps: vec![],
+            total_seconds: 0.0,
+            error: None,
+        })
+    }
+
+    // For a migration failing before its plan is made, only the error is known.
+    pub fn unplanned(chain_name: &str, chain_dir: &Path, out_dir: &Path) -> Self {
+        Self {
+            chain_name: chain_name.to_string(),
+            chain_dir: chain_dir.to_path_buf(),
+            out_dir: out_dir.to_path_buf(),
+            dry_run: false,
+            source_version: String::new(),
+            migration_path: String::new(),
+            ca_cert: None,
+            meta: MetaReport {
+                new_dir: out_dir.join(chain_name),
+                files: vec![],
+            },
+            nodes: vec![],
+            membership: Membership::default(),
+            warnings: vec![],
+            steps: vec![],
+            total_seconds: 0.0,
+            error: None,
+        }
+    }
+
+    // Steps are named after the paths they write in `staging_dir`, report them under `out_dir`.
+    pub fn set_steps(&mut self, timings: &[StepTiming], staging_dir: &Path) {
+        let staged = format!("`{}", staging_dir.to_string_lossy());
+        let rebased = format!("`{}", self.out_dir.to_string_lossy());
+        self.steps = timings
+            .iter()
+            .map(|t| StepTiming {
+                step: t.step.replacen(&staged, &rebased, 1),
+                ..t.clone()
+            })
+            .collect();
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).context("cannot serialize report")?;
+        fs::write(path, content)
+            .with_context(|| format!("cannot write report `{}`", path.to_string_lossy()))
+    }
+}
+
+fn cert_report(pem: &str) -> Result<CertReport> {
+    let (_, pem) =
+        parse_x509_pem(pem.as_bytes()).map_err(|e| anyhow!("invalid cert pem: {}", e))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| anyhow!("invalid cert: {}", e))?;
+    let validity = cert.validity();
+    Ok(CertReport {
+        sha256_fingerprint: hex::encode(Sha256::digest(&pem.contents)),
+        not_before: validity.not_before.to_rfc2822(),
+        not_after: validity.not_after.to_rfc2822(),
+    })
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::inventory::inventory;
+use crate::inventory::Fate;
+use crate::migrate::extract_text;
+use crate::migrate::extract_toml;
+use crate::migrate::load_node_dirs;
+use crate::migrate::new;
+use crate::migrate::new_node_dir;
+use crate::migrate::old;
+use crate::migrate::CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS;
+use crate::migrate::DISCARDED_FILES_AND_DIRS;
+use crate::migrate::LOG4RS_AND_KMS_DB_FILES;
+use crate::plan::ensure_enough_space;
+use crate::plan::CopyOp;
+use crate::progress::NodeProgress;
+use crate::running;
+use crate::staging;
+use crate::staging::OnConflict;
+use crate::transfer::free_space;
+use crate::transfer::move_dir;
+use crate::transfer::same_filesystem;
+use crate::transfer::DataMode;
+use crate::transfer::Fallback;
+use crate::transfer::Transfer;
+
+pub struct RollbackOpts {
+    // How to transfer `chain_data`, `data` and `logs` back.
+    pub transfer: Transfer,
+    // What to do with old node dirs still in the old chain dir.
+    pub on_conflict: OnConflict,
+    // Roll back even if the new chain seems to be running.
+    pub force: bool,
+}
+
+struct RollbackNode {
+    new_dir: PathBuf,
+    // Where the 6.1.0 node dir is rebuilt.
+    old_dir: PathBuf,
+    config: new::Config,
+}
+
+// Rebuild the 6.1.0 node dirs in `chain_data_dir` from the migrated chain in `new_chain_data_dir`.
+// The old metadata dir is left as is, it's never touched by the migration.
+pub fn rollback<P, Q>(
+    new_chain_data_dir: P,
+    chain_data_dir: Q,
+    chain_name: &str,
+    opts: &RollbackOpts,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let new_chain_data_dir = new_chain_data_dir.as_ref();
+    let chain_data_dir = chain_data_dir.as_ref();
+
+    let old_meta_dir = chain_data_dir.join(chain_name);
+    ensure!(
+        old_meta_dir.is_dir(),
+        "old chain metadata dir `{}` not found, restore it from a backup first",
+        old_meta_dir.to_string_lossy()
+    );
+
+    let mut nodes = load_nodes(new_chain_data_dir, chain_data_dir, chain_name)?;
+    running::ensure_stopped(
+        nodes.iter().map(|n| (n.new_dir.as_path(), &n.config)),
+        opts.force,
+    )?;
+
+    let conflicts = staging::conflicts(chain_data_dir, nodes.iter().map(|n| n.old_dir.as_path()));
+    match opts.on_conflict {
+        OnConflict::Fail => staging::ensure_no_conflicts(&conflicts)?,
+        OnConflict::Overwrite => {
+            for d in &conflicts {
+                eprintln!("warning: `{}` will be overwritten", d.to_string_lossy());
+            }
+        }
+        OnConflict::Skip => {
+            for d in &conflicts {
+                println!("`{}` already exists, skip", d.to_string_lossy());
+            }
+            nodes.retain(|n| !conflicts.contains(&n.old_dir));
+        }
+    }
+
+    eprintln!(
+        "warning: blocks and state written by the 6.3.0 chain since the upgrade are carried back as they are, \
+        make sure 6.1.0 can still read them, or restore a backup taken before the upgrade instead"
+    );
+    // Old node dirs still around give them back, see `plan_node`.
+    for n in &nodes {
+        let missing: Vec<String> = DISCARDED_FILES_AND_DIRS
+            .iter()
+            .filter(|f| !n.old_dir.join(f).exists())
+            .map(|f| format!("`{}`", f))
+            .collect();
+        if !missing.is_empty() {
+            eprintln!(
+                "warning: `{}` is rebuilt without {}, they can't be re-derived, take them from a backup if needed",
+                n.old_dir.to_string_lossy(),
+                missing.join(", ")
+            );
+        }
+    }
+
+    // Built aside and renamed into place, like the migration.
+    let staging_dir = staging::staging_dir(chain_data_dir)?;
+    ensure!(
+        !staging_dir.exists(),
+        "staging dir `{}` already exists, remove it first",
+        staging_dir.to_string_lossy()
+    );
+    let plans = nodes
+        .iter()
+        .map(|n| plan_node(n, &staging_dir, opts.transfer))
+        .collect::<Result<Vec<_>>>()?;
+    let space_needed = plans.iter().flatten().map(|op| op.space).sum();
+    ensure_enough_space(space_needed, free_space(&staging_dir)?, chain_data_dir)?;
+
+    for (node, copies) in nodes.iter().zip(&plans) {
+        let staged_dir = staging_dir.join(node.old_dir.file_name().unwrap());
+        if let Err(e) = rebuild_node(node, &staged_dir, copies) {
+            let all_moved_back = undo_moves(&plans);
+            staging::discard(
+                &staging_dir,
+                (!all_moved_back)
+                    .then_some("it holds data moved from the new chain, move it back by hand"),
+            );
+            return Err(e);
+        }
+        println!(
+            "`{}` rolled back to `{}`",
+            node.new_dir.to_string_lossy(),
+            node.old_dir.to_string_lossy()
+        );
+    }
+    staging::commit(&staging_dir, chain_data_dir, opts.on_conflict)
+}
+
+// The migrated nodes, and where each one's 6.1.0 node dir goes.
+fn load_nodes(
+    new_chain_data_dir: &Path,
+    chain_data_dir: &Path,
+    chain_name: &str,
+) -> Result<Vec<RollbackNode>> {
+    let meta_dir = new_chain_data_dir.join(chain_name);
+    let meta_config: new::MetaConfig =
+        extract_toml(&meta_dir, "config.toml").with_context(|| {
+            format!(
+                "cannot load new chain metadata in `{}`",
+                meta_dir.to_string_lossy()
+            )
+        })?;
+
+    // Old node dirs still around keep their names,
+    // the others are named after their order, as the migration sorted them.
+    let mut existing: HashMap<String, PathBuf> = HashMap::new();
+    if chain_data_dir.is_dir() {
+        for d in load_node_dirs(chain_data_dir, chain_name, &[])?.dirs {
+            if let Ok(addr) = extract_text(&d, "node_address") {
+                existing.insert(addr, d);
+            }
+        }
+    }
+
+    let mut nodes: Vec<RollbackNode> = vec![];
+    for (i, addr) in meta_config.current_config.addresses.iter().enumerate() {
+        let new_dir = new_node_dir(new_chain_data_dir, chain_name, addr)?;
+        let config: new::Config = extract_toml(&new_dir, "config.toml").with_context(|| {
+            format!(
+                "cannot load new node config in `{}`",
+                new_dir.to_string_lossy()
+            )
+        })?;
+        let old_dir = match existing.get(addr) {
+            Some(d) => d.clone(),
+            None => chain_data_dir.join(format!("{}-{}", chain_name, i)),
+        };
+        if let Some(other) = nodes.iter().find(|n| n.old_dir == old_dir) {
+            bail!(
+                "both `{}` and `{}` would be rolled back to `{}`",
+                other.new_dir.to_string_lossy(),
+                new_dir.to_string_lossy(),
+                old_dir.to_string_lossy()
+            );
+        }
+        nodes.push(RollbackNode {
+            new_dir,
+            old_dir,
+            config,
+        });
+    }
+    Ok(nodes)
+}
+
+fn plan_node(node: &RollbackNode, staging_dir: &Path, transfer: Transfer) -> Result<Vec<CopyOp>> {
+    let staged_dir = staging_dir.join(node.old_dir.file_name().unwrap());
+    if transfer.mode.needs_same_filesystem() && transfer.fallback == Fallback::Fail {
+        ensure!(
+            same_filesystem(&node.new_dir, &staged_dir)?,
+            "cannot {} data from `{}` to `{}` across filesystems, \
+            use `--data-mode-fallback copy` or another data mode",
+            transfer.mode,
+            node.new_dir.to_string_lossy(),
+            node.old_dir.to_string_lossy()
+        );
+    }
+
+    let mut copies = LOG4RS_AND_KMS_DB_FILES
+        .iter()
+        .map(|f| CopyOp::file(node.new_dir.join(f), staged_dir.join(f)))
+        .collect::<Result<Vec<_>>>()?;
+    for d in CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS {
+        copies.push(CopyOp::dir(
+            node.new_dir.join(d),
+            staged_dir.join(d),
+            transfer,
+        )?);
+    }
+    // The old node dir being replaced still has what the migration left behind,
+    // e.g. the keys and raft data 6.1.0 needs, keep it and anything unknown.
+    if node.old_dir.is_dir() {
+        for e in inventory(&node.old_dir)? {
+            if !DISCARDED_FILES_AND_DIRS.contains(&e.name.as_str()) && e.fate != Fate::Unknown {
+                continue;
+            }
+            let (from, to) = (node.old_dir.join(&e.name), staged_dir.join(&e.name));
+            copies.push(if from.is_dir() {
+                CopyOp::dir(from, to, transfer)?
+            } else {
+                CopyOp::file(from, to)?
+            });
+        }
+    }
+    Ok(copies)
+}
+
+fn rebuild_node(node: &RollbackNode, staged_dir: &Path, copies: &[CopyOp]) -> Result<()> {
+    fs::create_dir_all(staged_dir)
+        .with_context(|| format!("cannot create dir `{}`", staged_dir.to_string_lossy()))?;
+    // The originals still in the old node dir are kept, only missing ones are re-derived.
+    for (file_name, content) in old_configs(&node.config)? {
+        let (original, path) = (node.old_dir.join(file_name), staged_dir.join(file_name));
+        if original.is_file() {
+            fs::copy(&original, &path).with_context(|| {
+                format!(
+                    "cannot copy file from `{}` to `{}`",
+                    original.to_string_lossy(),
+                    path.to_string_lossy()
+                )
+            })?;
+        } else {
+            fs::write(&path, content)
+                .with_context(|| format!("cannot write `{}`", path.to_string_lossy()))?;
+        }
+    }
+    let progress = NodeProgress::untracked();
+    for op in copies {
+        op.execute(false, &progress.op(op))?;
+    }
+    Ok(())
+}
+
+// Put what's been moved back to the new chain, false if some of it is stuck in staging.
+fn undo_moves(plans: &[Vec<CopyOp>]) -> bool {
+    let mut all_moved_back = true;
+    for op in plans.iter().flatten() {
+        if op.transfer.mode == DataMode::Move && !op.from.exists() && op.to.exists() {
+            if let Err(e) = move_dir(&op.to, &op.from) {
+                eprintln!(
+                    "warning: cannot move `{}` back: {:#}",
+                    op.to.to_string_lossy(),
+                    e
+                );
+                all_moved_back = false;
+            }
+        }
+    }
+    all_moved_back
+}
+
+// Re-derive the 6.1.0 config files from the new node config, the inverse of `NodeConfigMigrate`.
+fn old_configs(config: &new::Config) -> Result<Vec<(&'static str, String)>> {
+    let controller = old::ControllerConfig {
+        network_port: config.controller.network_port,
+        consensus_port: config.controller.consensus_port,
+        storage_port: config.controller.storage_port,
+        kms_port: config.controller.kms_port,
+        executor_port: config.controller.executor_port,
+    };
+    // 6.1.0 validators are node addresses, overlord ones are keys.
+    ensure!(
+        !config.consensus.kind().needs_validator_keys(),
+        "cannot roll back `{}` with {} consensus, its validators are keys unknown to 6.1.0, \
+        restore a backup instead",
+        config.controller.node_address,
+        config.consensus.kind()
+    );
+    let consensus = old::ConsensusConfig {
+        controller_port: config.consensus.controller_port(),
+    };
+    let network = old::NetworkConfig {
+        port: config.network.listen_port(),
+        peers: config
+            .network
+            .peer_addrs()?
+            .into_iter()
+            .map(|(ip, port)| old::PeerConfig { ip, port })
+            .collect(),
+    };
+    let system_config = old::InitSysConfig {
+        version: config.system_config.version,
+        admin: config.system_config.admin.clone(),
+        block_interval: config.system_config.block_interval,
+        chain_id: config.system_config.chain_id.clone(),
+        validators: config.system_config.validators.clone(),
+    };
+    let genesis = old::Genesis {
+        timestamp: config.genesis_block.timestamp,
+        prevhash: config.genesis_block.prevhash.clone(),
+    };
+
+    Ok(vec![
+        ("controller-config.toml", to_toml(&controller)?),
+        ("consensus-config.toml", to_toml(&consensus)?),
+        ("network-config.toml", to_toml(&network)?),
+        ("init_sys_config.toml", to_toml(&system_config)?),
+        ("genesis.toml", to_toml(&genesis)?),
+        ("key_id", config.controller.key_id.to_string()),
+        ("key_file", config.kms.db_key.clone()),
+        ("node_address", config.controller.node_address.clone()),
+    ])
+}
+
+fn to_toml<T: Serialize>(v: &T) -> Result<String> {
+    toml::to_string_pretty(v).context("cannot serialize old config")
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Result;
+
+use crate::migrate::new;
+use crate::migrate::CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS;
+
+// Refuse to go on with nodes that look running, unless forced.
+// `nodes` are the node dirs with their configs.
+pub fn ensure_stopped<'a, I>(nodes: I, force: bool) -> Result<()>
+where
+    I: IntoIterator<Item = (&'a Path, &'a new::Config)>,
+{
+    let mut found = vec![];
+    for (node_dir, config) in nodes {
+        for sign in check_running(node_dir, config) {
+            found.push(format!("`{}`: {}", node_dir.to_string_lossy(), sign));
+        }
+    }
+    if found.is_empty() {
+        return Ok(());
+    }
+    if force {
+        for f in &found {
+            eprintln!("warning: node may be running, {}", f);
+        }
+        return Ok(());
+    }
+    bail!(
+        "the chain seems to be running, stop it first or use `--force`:\n  {}",
+        found.join("\n  ")
+    )
+}
+
+// Signs that the old node in `node_dir` is still running, empty if none.
+// Copying the RocksDB of a running node gives inconsistent data.
+pub fn check_running(node_dir: &Path, config: &new::Config) -> Vec<String> {
+    let mut signs = vec![];
+
+    let c = &config.controller;
+    let ports = [
+        c.controller_port,
+        c.consensus_port,
+        c.executor_port,
+        c.storage_port,
+        c.kms_port,
+        c.network_port,
+        config.network.listen_port(),
+    ];
+    for port in ports {
+        if let Err(e) = TcpListener::bind(("0.0.0.0", port)) {
+            if e.kind() == io::ErrorKind::AddrInUse {
+                signs.push(format!("port {} is in use", port));
+            }
+        }
+    }
+
+    // `logs` has no RocksDB in it.
+    for d in CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS
+        .iter()
+        .filter(|d| **d != "logs")
+    {
+        for lock in find_files(&node_dir.join(d), |name| name == "LOCK") {
+            if let Some(pid) = lock_holder(&lock) {
+                signs.push(format!(
+                    "RocksDB lock `{}` is held by process {}",
+                    lock.to_string_lossy(),
+                    pid
+                ));
+            }
+        }
+    }
+
+    for pid_file in find_files(node_dir, |name| name.ends_with(".pid")) {
+        let pid = fs::read_to_string(&pid_file)
+            .ok()
+            .and_then(|s| s.trim().parse::<i32>().ok());
+        if let Some(pid) = pid.filter(|pid| is_alive(*pid)) {
+            signs.push(format!(
+                "process {} in pid file `{}` is alive",
+                pid,
+                pid_file.to_string_lossy()
+            ));
+        }
+    }
+
+    signs
+}
+
+// Files under `dir` whose names match, unreadable dirs are ignored.
+fn find_files<F>(dir: &Path, matches: F) -> Vec<PathBuf>
+where
+    F: Fn(&str) -> bool + Copy,
+{
+    let mut found = vec![];
+    if let Ok(entries) = fs::read_dir(dir) {
+        for ent in entries.flatten() {
+            let path = ent.path();
+            if path.is_dir() {
+                found.extend(find_files(&path, matches));
+            } else if ent.file_name().to_str().is_some_and(matches) {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+// RocksDB holds a POSIX record lock on its `LOCK` file while open.
+#[cfg(unix)]
+fn lock_holder(lock: &Path) -> Option<i32> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    let file = OpenOptions::new().read(true).write(true).open(lock).ok()?;
+    // SAFETY: `flock` is plain old data, all zeros is a valid value.
+    let mut fl: libc::flock = unsafe { std::mem::zeroed() };
+    fl.l_type = libc::F_WRLCK as _;
+    fl.l_whence = libc::SEEK_SET as _;
+    // SAFETY: the fd is valid and `fl` outlives the call.
+    let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut fl) };
+    if ret == -1 || i32::from(fl.l_type) == libc::F_UNLCK {
+        return None;
+    }
+    Some(fl.l_pid)
+}
+
+#[cfg(not(unix))]
+fn lock_holder(_lock: &Path) -> Option<i32> {
+    None
+}
+
+#[cfg(unix)]
+fn is_alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks for the existence of the process.
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: i32) -> bool {
+    false
+}
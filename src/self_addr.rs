@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::migrate::new;
+
+// Hints for finding out node's own host and port for peers to connect.
+#[derive(Default)]
+pub struct SelfAddrHints {
+    // node index -> (host, port), loaded from the mapping file.
+    pub mapping: HashMap<usize, (String, u16)>,
+    // Host shared by all nodes, the port is the node's network listen port.
+    pub host: Option<String>,
+}
+
+impl SelfAddrHints {
+    // The mapping file is a toml table from node index to `host:port`, e.g.
+    // ```toml
+    // 0 = "192.168.1.10:40000"
+    // 1 = "192.168.1.11:40000"
+    // ```
+    pub fn load(mapping_file: Option<&Path>, host: Option<String>) -> Result<Self> {
+        let mapping = match mapping_file {
+            Some(path) => {
+                let s = fs::read_to_string(path).with_context(|| {
+                    format!(
+                        "cannot read self address mapping file `{}`",
+                        path.to_string_lossy()
+                    )
+                })?;
+                let raw: HashMap<String, String> =
+                    toml::from_str(&s).context("invalid self address mapping file")?;
+                raw.into_iter()
+                    .map(|(index, addr)| {
+                        let index = index
+                            .parse()
+                            .with_context(|| format!("invalid node index `{}`", index))?;
+                        let (host, port) = parse_host_port(&addr)?;
+                        Ok((index, (host, port)))
+                    })
+                    .collect::<Result<_>>()?
+            }
+            None => HashMap::new(),
+        };
+        Ok(Self { mapping, host })
+    }
+}
+
+fn parse_host_port(addr: &str) -> Result<(String, u16)> {
+    let (host, port) = addr
+        .rsplit_once(':')
+        .with_context(|| format!("invalid address `{}`, must be `host:port`", addr))?;
+    let port = port
+        .parse()
+        .with_context(|| format!("invalid port in address `{}`", addr))?;
+    Ok((host.to_string(), port))
+}
+
+// Find out every node's own host and port.
+//
+// In order of preference:
+// 1. the entry in the mapping file,
+// 2. the `--host` hint with the node's network listen port,
+// 3. inferred from peers, only when unambiguous.
+pub fn resolve_self_addrs(
+    node_configs: &[new::Config],
+    hints: &SelfAddrHints,
+) -> Result<Vec<(String, u16)>> {
+    // Every node contains host and port for peers execept itself.
+    // So the union of all peers is the full set if the peer lists are sane.
+    let peer_addrs = node_configs
+        .iter()
+        .map(|c| c.network.peer_addrs())
+        .collect::<Result<Vec<_>>>()?;
+    let full_peer_set: HashSet<(String, u16)> = peer_addrs.iter().flatten().cloned().collect();
+
+    let self_addrs = node_configs
+        .iter()
+        .zip(&peer_addrs)
+        .enumerate()
+        .map(|(i, (c, peers))| {
+            if let Some(addr) = hints.mapping.get(&i) {
+                return Ok(addr.clone());
+            }
+            if let Some(host) = &hints.host {
+                return Ok((host.clone(), c.network.listen_port()));
+            }
+            infer_self_addr(c, peers, &full_peer_set).with_context(|| {
+                format!(
+                    "cannot find out self host and port for node {} `{}`",
+                    i, c.controller.node_address
+                )
+            })
+        })
+        .collect::<Result<Vec<(String, u16)>>>()?;
+
+    let mut seen = HashSet::new();
+    for (host, port) in &self_addrs {
+        if !seen.insert((host, port)) {
+            bail!(
+                "more than one node has the self address `{}:{}`. \
+                Use a self address mapping file to tell them apart",
+                host,
+                port
+            );
+        }
+    }
+
+    Ok(self_addrs)
+}
+
+fn infer_self_addr(
+    c: &new::Config,
+    peers: &[(String, u16)],
+    full_peer_set: &HashSet<(String, u16)>,
+) -> Result<(String, u16)> {
+    let peer_set: HashSet<(String, u16)> = peers.iter().cloned().collect();
+    let mut candidates: Vec<(String, u16)> = full_peer_set.difference(&peer_set).cloned().collect();
+    // Node's peer list might be incomplete, try to narrow it down by its listen port.
+    if candidates.len() > 1 {
+        candidates.retain(|(_, port)| *port == c.network.listen_port());
+    }
+    match candidates.len() {
+        1 => Ok(candidates.remove(0)),
+        0 => bail!(
+            "no candidate found. The assumption that node's peers info \
+            contains all (and only) other peers has been violated. \
+            Use `--host` or a self address mapping file instead"
+        ),
+        _ => {
+            candidates.sort();
+            let candidates: Vec<String> = candidates
+                .iter()
+                .map(|(host, port)| format!("{}:{}", host, port))
+                .collect();
+            bail!(
+                "ambiguous candidates [{}]. \
+                Use `--host` or a self address mapping file instead",
+                candidates.join(", ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::ConsensusKind;
+    use crate::migrate::old;
+    use crate::migrate::NodeConfigMigrate;
+    use crate::network::NetworkKind;
+
+    // A node listening on `port` with `peers` in its network config.
+    fn node(port: u16, peers: &[(&str, u16)]) -> new::Config {
+        NodeConfigMigrate {
+            controller_port: 50004,
+            consensus_port: 50001,
+            executor_port: 50002,
+            network_port: 50000,
+            kms_port: 50005,
+            storage_port: 50003,
+            node_addr: format!("0x{:040x}", port),
+            genesis_block: old::Genesis {
+                timestamp: 0,
+                prevhash: "0x00".to_string(),
+            },
+            system_config: old::InitSysConfig {
+                version: 0,
+                admin: "0x00".to_string(),
+                block_interval: 3,
+                chain_id: "0x00".to_string(),
+                validators: vec![],
+            },
+            kms_password: String::new(),
+            key_id: 1,
+            network_config: old::NetworkConfig {
+                port,
+                peers: peers
+                    .iter()
+                    .map(|(ip, port)| old::PeerConfig {
+                        ip: ip.to_string(),
+                        port: *port,
+                    })
+                    .collect(),
+            },
+        }
+        .generate_new(ConsensusKind::default(), NetworkKind::default())
+    }
+
+    fn addr(host: &str, port: u16) -> (String, u16) {
+        (host.to_string(), port)
+    }
+
+    fn infer(c: &new::Config, all: &[&new::Config]) -> Result<(String, u16)> {
+        let full_peer_set = all
+            .iter()
+            .flat_map(|c| c.network.peer_addrs().unwrap())
+            .collect();
+        infer_self_addr(c, &c.network.peer_addrs()?, &full_peer_set)
+    }
+
+    #[test]
+    fn parse_host_port_splits_at_the_last_colon() {
+        assert_eq!(
+            parse_host_port("192.168.1.10:40000").unwrap(),
+            addr("192.168.1.10", 40000)
+        );
+        assert_eq!(parse_host_port("node0:1").unwrap(), addr("node0", 1));
+        assert_eq!(parse_host_port("::1:40000").unwrap(), addr("::1", 40000));
+        assert!(parse_host_port("192.168.1.10").is_err());
+        assert!(parse_host_port("192.168.1.10:port").is_err());
+        assert!(parse_host_port("192.168.1.10:65536").is_err());
+    }
+
+    #[test]
+    fn infer_the_peer_missing_from_own_list() {
+        let n0 = node(40000, &[("127.0.0.1", 40001), ("127.0.0.1", 40002)]);
+        let n1 = node(40001, &[("127.0.0.1", 40000), ("127.0.0.1", 40002)]);
+        let n2 = node(40002, &[("127.0.0.1", 40000), ("127.0.0.1", 40001)]);
+        let all = [&n0, &n1, &n2];
+        assert_eq!(infer(&n0, &all).unwrap(), addr("127.0.0.1", 40000));
+        assert_eq!(infer(&n1, &all).unwrap(), addr("127.0.0.1", 40001));
+        assert_eq!(infer(&n2, &all).unwrap(), addr("127.0.0.1", 40002));
+    }
+
+    #[test]
+    fn infer_narrows_down_by_listen_port() {
+        // `n0` misses a peer, which leaves two candidates.
+        let n0 = node(40000, &[("10.0.0.1", 40001)]);
+        let n1 = node(40001, &[("10.0.0.0", 40000), ("10.0.0.2", 40002)]);
+        let n2 = node(40002, &[("10.0.0.0", 40000), ("10.0.0.1", 40001)]);
+        assert_eq!(
+            infer(&n0, &[&n0, &n1, &n2]).unwrap(),
+            addr("10.0.0.0", 40000)
+        );
+    }
+
+    #[test]
+    fn infer_fails_if_ambiguous() {
+        // Same port on every host, and `n0` misses a peer.
+        let n0 = node(40000, &[("10.0.0.1", 40000)]);
+        let n1 = node(40000, &[("10.0.0.0", 40000), ("10.0.0.2", 40000)]);
+        let n2 = node(40000, &[("10.0.0.0", 40000), ("10.0.0.1", 40000)]);
+        let e = infer(&n0, &[&n0, &n1, &n2]).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("ambiguous candidates [10.0.0.0:40000, 10.0.0.2:40000]"));
+    }
+
+    #[test]
+    fn infer_fails_without_candidates() {
+        // Single node chain, nobody knows its address.
+        let n0 = node(40000, &[]);
+        let e = infer(&n0, &[&n0]).unwrap_err();
+        assert!(e.to_string().starts_with("no candidate found"));
+    }
+
+    #[test]
+    fn resolve_prefers_mapping_then_host() {
+        let nodes = [
+            node(40000, &[("127.0.0.1", 40001)]),
+            node(40001, &[("127.0.0.1", 40000)]),
+        ];
+        assert_eq!(
+            resolve_self_addrs(&nodes, &SelfAddrHints::default()).unwrap(),
+            [addr("127.0.0.1", 40000), addr("127.0.0.1", 40001)]
+        );
+
+        let hints = SelfAddrHints {
+            mapping: HashMap::from([(1, addr("node1", 41000))]),
+            host: Some("node".to_string()),
+        };
+        assert_eq!(
+            resolve_self_addrs(&nodes, &hints).unwrap(),
+            [addr("node", 40000), addr("node1", 41000)]
+        );
+    }
+
+    #[test]
+    fn resolve_fails_on_shared_self_addr() {
+        let nodes = [
+            node(40000, &[("127.0.0.1", 40000)]),
+            node(40000, &[("127.0.0.1", 40000)]),
+        ];
+        let hints = SelfAddrHints {
+            mapping: HashMap::new(),
+            host: Some("127.0.0.1".to_string()),
+        };
+        let e = resolve_self_addrs(&nodes, &hints).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("more than one node has the self address `127.0.0.1:40000`"));
+    }
+}
@@ -0,0 +1,209 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::slice;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::journal::Journal;
+use crate::journal::JOURNAL_FILE;
+use crate::transfer::DataMode;
+
+// The output is built in a hidden sibling of the out dir, and only renamed
+// into place once complete. So every dir in the out dir is either absent or complete.
+pub fn staging_dir(out_dir: &Path) -> Result<PathBuf> {
+    let name = out_dir.file_name().with_context(|| {
+        format!(
+            "invalid out dir `{}`, must end with a dir name",
+            out_dir.to_string_lossy()
+        )
+    })?;
+    Ok(out_dir.with_file_name(format!(".{}.staging", name.to_string_lossy())))
+}
+
+// What to do with meta and node dirs already in the out dir.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OnConflict {
+    #[default]
+    Fail,
+    Overwrite,
+    Skip,
+}
+
+impl OnConflict {
+    pub const VARIANTS: [&'static str; 3] = ["fail", "overwrite", "skip"];
+}
+
+impl FromStr for OnConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "overwrite" => Ok(Self::Overwrite),
+            "skip" => Ok(Self::Skip),
+            _ => bail!("unknown conflict policy `{}`", s),
+        }
+    }
+}
+
+// Those of `dirs` already in the out dir, by their names.
+pub fn conflicts<'a, I>(out_dir: &Path, dirs: I) -> Vec<PathBuf>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    dirs.into_iter()
+        .filter_map(|d| d.file_name())
+        .map(|name| out_dir.join(name))
+        .filter(|d| d.exists())
+        .collect()
+}
+
+pub fn ensure_no_conflicts(conflicts: &[PathBuf]) -> Result<()> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    let list: Vec<String> = conflicts
+        .iter()
+        .map(|d| format!("  `{}`", d.to_string_lossy()))
+        .collect();
+    bail!(
+        "these dirs already exist, use `--on-conflict overwrite` or `--on-conflict skip`:\n{}",
+        list.join("\n")
+    )
+}
+
+// Move the complete output into place.
+pub fn commit(staging_dir: &Path, out_dir: &Path, on_conflict: OnConflict) -> Result<()> {
+    // Nothing to merge with, rename it as a whole.
+    if !out_dir.exists() {
+        remove_journal(staging_dir)?;
+        return fs::rename(staging_dir, out_dir).with_context(|| {
+            format!(
+                "cannot rename staging dir `{}` to `{}`",
+                staging_dir.to_string_lossy(),
+                out_dir.to_string_lossy()
+            )
+        });
+    }
+
+    // Otherwise move dirs one by one, each of them is still complete or absent.
+    let entries = fs::read_dir(staging_dir)
+        .with_context(|| format!("cannot read dir `{}`", staging_dir.to_string_lossy()))?;
+    for ent in entries {
+        let ent =
+            ent.with_context(|| format!("cannot read dir `{}`", staging_dir.to_string_lossy()))?;
+        if ent.file_name() == JOURNAL_FILE {
+            continue;
+        }
+        let target = out_dir.join(ent.file_name());
+        let replaced = out_dir.join(format!(".{}.replaced", ent.file_name().to_string_lossy()));
+        let exists = target.exists();
+        if exists {
+            match on_conflict {
+                OnConflict::Fail => ensure_no_conflicts(slice::from_ref(&target))?,
+                OnConflict::Skip => continue,
+                OnConflict::Overwrite => fs::rename(&target, &replaced)
+                    .with_context(|| format!("cannot move away `{}`", target.to_string_lossy()))?,
+            }
+        }
+        fs::rename(ent.path(), &target).with_context(|| {
+            format!(
+                "cannot rename `{}` to `{}`",
+                ent.path().to_string_lossy(),
+                target.to_string_lossy()
+            )
+        })?;
+        if exists {
+            fs::remove_dir_all(&replaced)
+                .with_context(|| format!("cannot remove `{}`", replaced.to_string_lossy()))?;
+        }
+    }
+    fs::remove_dir_all(staging_dir).with_context(|| {
+        format!(
+            "cannot remove staging dir `{}`",
+            staging_dir.to_string_lossy()
+        )
+    })
+}
+
+fn remove_journal(staging_dir: &Path) -> Result<()> {
+    let journal = staging_dir.join(JOURNAL_FILE);
+    if journal.exists() {
+        fs::remove_file(&journal)
+            .with_context(|| format!("cannot remove journal `{}`", journal.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+// Why the staging dir of a failed migration is worth keeping for a rerun, if it is.
+// Data moved from the old chain can't be thrown away, steps completed according to
+// the journal are skipped when resuming, and `--data-mode delta` picks up the data
+// already synced, by `presync` or by the failed run. So a rerun needn't start over.
+pub fn keep_reason(staging_dir: &Path, mode: DataMode) -> Option<&'static str> {
+    if mode == DataMode::Move {
+        return Some(concat!(
+            "it holds data moved from the old chain, ",
+            "rerun the same command to resume"
+        ));
+    }
+    if matches!(Journal::load(staging_dir), Ok(Some(j)) if !j.completed.is_empty()) {
+        return Some(concat!(
+            "its journal has completed steps and the certs, ",
+            "rerun the same command to resume, or remove it to start over"
+        ));
+    }
+    if mode == DataMode::Delta && has_data(staging_dir) {
+        return Some(concat!(
+            "it holds the data synced for `--data-mode delta`, ",
+            "rerun the same command to resume, or remove it to start over"
+        ));
+    }
+    None
+}
+
+// Data presynced into the staging dir is only picked up by `--data-mode delta`,
+// refuse to copy it all over again otherwise. Once a migration has started,
+// what's there is its own and the journal tells how to resume.
+pub fn ensure_presync_picked_up(staging_dir: &Path, mode: DataMode) -> Result<()> {
+    if mode == DataMode::Delta || staging_dir.join(JOURNAL_FILE).exists() {
+        return Ok(());
+    }
+    ensure!(
+        !has_data(staging_dir),
+        "staging dir `{}` holds data presynced by `presync`, use `--data-mode delta` \
+        to pick it up, or remove it to start over",
+        staging_dir.to_string_lossy()
+    );
+    Ok(())
+}
+
+// Anything but the journal in the staging dir.
+fn has_data(staging_dir: &Path) -> bool {
+    fs::read_dir(staging_dir)
+        .map(|entries| entries.flatten().any(|e| e.file_name() != JOURNAL_FILE))
+        .unwrap_or(false)
+}
+
+// Clean up after a failed run, unless there's a reason to keep the staging dir.
+pub fn discard(staging_dir: &Path, keep_reason: Option<&str>) {
+    if let Some(reason) = keep_reason {
+        eprintln!(
+            "warning: keep staging dir `{}` since {}",
+            staging_dir.to_string_lossy(),
+            reason
+        );
+        return;
+    }
+    if let Err(e) = fs::remove_dir_all(staging_dir) {
+        eprintln!(
+            "warning: cannot remove staging dir `{}`: {}",
+            staging_dir.to_string_lossy(),
+            e
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+// How node data dirs are transferred to the new node dir.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DataMode {
+    #[default]
+    Copy,
+    Hardlink,
+    Reflink,
+    Move,
+    // Only copy what's new or changed since the last copy, e.g. by `presync`.
+    Delta,
+}
+
+impl DataMode {
+    pub const VARIANTS: [&'static str; 5] = ["copy", "hardlink", "reflink", "move", "delta"];
+
+    // Whether the mode only works within a filesystem.
+    pub fn needs_same_filesystem(self) -> bool {
+        matches!(self, Self::Hardlink | Self::Reflink | Self::Move)
+    }
+}
+
+impl FromStr for DataMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "copy" => Ok(Self::Copy),
+            "hardlink" => Ok(Self::Hardlink),
+            "reflink" => Ok(Self::Reflink),
+            "move" => Ok(Self::Move),
+            "delta" => Ok(Self::Delta),
+            _ => bail!("unknown data mode `{}`", s),
+        }
+    }
+}
+
+impl fmt::Display for DataMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Copy => "copy",
+            Self::Hardlink => "hardlink",
+            Self::Reflink => "reflink",
+            Self::Move => "move",
+            Self::Delta => "delta",
+        };
+        f.write_str(s)
+    }
+}
+
+// What to do if the data mode doesn't work, e.g. hardlink across filesystems.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fallback {
+    Copy,
+    Fail,
+}
+
+impl Fallback {
+    pub const VARIANTS: [&'static str; 2] = ["copy", "fail"];
+}
+
+impl FromStr for Fallback {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "copy" => Ok(Self::Copy),
+            "fail" => Ok(Self::Fail),
+            _ => bail!("unknown fallback `{}`", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Transfer {
+    pub mode: DataMode,
+    pub fallback: Fallback,
+    // Compare files by content hash instead of size and mtime in delta mode.
+    pub checksum: bool,
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Self {
+            mode: DataMode::Copy,
+            fallback: Fallback::Fail,
+            checksum: false,
+        }
+    }
+}
+
+// Whether `from` and the would-be `to` are on the same filesystem.
+pub fn same_filesystem(from: &Path, to: &Path) -> Result<bool> {
+    Ok(device_of(from)? == device_of(nearest_existing(to)?)?)
+}
+
+// `path` may not exist yet, what's on the same filesystem is its nearest existing ancestor.
+// A relative path ends with an empty ancestor, which is the current dir.
+fn nearest_existing(path: &Path) -> Result<&Path> {
+    path.ancestors()
+        .map(|p| {
+            if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            }
+        })
+        .find(|p| p.exists())
+        .with_context(|| format!("no existing ancestor for `{}`", path.to_string_lossy()))
+}
+
+// Bytes available to unprivileged users on the filesystem of the would-be `path`,
+// `None` if unknown.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = nearest_existing(path)?;
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .with_context(|| format!("invalid path `{}`", existing.to_string_lossy()))?;
+    // SAFETY: `statvfs` is plain old data, all zeros is a valid value.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is nul terminated and `stat` outlives the call.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("cannot get free space of `{}`", existing.to_string_lossy()));
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn device_of(path: &Path) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    let meta =
+        fs::metadata(path).with_context(|| format!("cannot stat `{}`", path.to_string_lossy()))?;
+    Ok(meta.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_path: &Path) -> Result<u64> {
+    // Can't tell, assume the same.
+    Ok(0)
+}
+
+// Recreate the dir tree of `from` in `to`, linking every file with `link`.
+fn link_dir<F>(from: &Path, to: &Path, link: &F) -> io::Result<()>
+where
+    F: Fn(&Path, &Path) -> io::Result<()>,
+{
+    fs::create_dir_all(to)?;
+    for ent in fs::read_dir(from)? {
+        let ent = ent?;
+        let src = ent.path();
+        let dst = to.join(ent.file_name());
+        if ent.file_type()?.is_dir() {
+            link_dir(&src, &dst, link)?;
+        } else {
+            link(&src, &dst)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn hardlink_dir(from: &Path, to: &Path) -> Result<()> {
+    link_dir(from, to, &|src, dst| fs::hard_link(src, dst)).map_err(|e| {
+        anyhow!(e).context(format!(
+            "cannot hardlink dir from `{}` to `{}`",
+            from.to_string_lossy(),
+            to.to_string_lossy()
+        ))
+    })
+}
+
+pub fn reflink_dir(from: &Path, to: &Path) -> Result<()> {
+    link_dir(from, to, &reflink_file).map_err(|e| {
+        anyhow!(e).context(format!(
+            "cannot reflink dir from `{}` to `{}`",
+            from.to_string_lossy(),
+            to.to_string_lossy()
+        ))
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn reflink_file(from: &Path, to: &Path) -> io::Result<()> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    let src = File::open(from)?;
+    let dst = File::create(to)?;
+    // SAFETY: both fds are valid for the duration of the call.
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    if ret == -1 {
+        let err = io::Error::last_os_error();
+        drop(dst);
+        let _ = fs::remove_file(to);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_file(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink is only supported on linux",
+    ))
+}
+
+pub fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("cannot create dir `{}`", parent.to_string_lossy()))?;
+    }
+    fs::rename(from, to).with_context(|| {
+        format!(
+            "cannot move dir from `{}` to `{}`",
+            from.to_string_lossy(),
+            to.to_string_lossy()
+        )
+    })
+}
+
+// What a delta sync has done.
+#[derive(Default)]
+pub struct SyncStats {
+    pub copied_files: u64,
+    pub copied_bytes: u64,
+    pub removed: u64,
+}
+
+// Make `to` the same as `from`, copying only the files that are new or changed
+// and removing those gone from `from`. Files are compared by size and mtime,
+// or by content hash if `checksum` is set.
+//
+// `from` may be in use, files vanishing in the middle are skipped.
+// `on_synced` is told the size of every file once it's up to date, copied or not.
+pub fn sync_dir(
+    from: &Path,
+    to: &Path,
+    checksum: bool,
+    on_synced: &dyn Fn(u64),
+) -> Result<SyncStats> {
+    let mut stats = SyncStats::default();
+    sync_dir_into(from, to, checksum, on_synced, &mut stats)?;
+    Ok(stats)
+}
+
+fn sync_dir_into(
+    from: &Path,
+    to: &Path,
+    checksum: bool,
+    on_synced: &dyn Fn(u64),
+    stats: &mut SyncStats,
+) -> Result<()> {
+    fs::create_dir_all(to)
+        .with_context(|| format!("cannot create dir `{}`", to.to_string_lossy()))?;
+
+    let mut synced = HashSet::new();
+    let entries = fs::read_dir(from)
+        .with_context(|| format!("cannot read dir `{}`", from.to_string_lossy()))?;
+    for ent in entries {
+        let ent = ent.with_context(|| format!("cannot read dir `{}`", from.to_string_lossy()))?;
+        let src = ent.path();
+        let dst = to.join(ent.file_name());
+        let src_meta = match ent.metadata() {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(anyhow!(e).context(format!("cannot stat `{}`", src.to_string_lossy())))
+            }
+        };
+
+        if src_meta.is_dir() {
+            if dst.is_file() {
+                remove(&dst)?;
+            }
+            sync_dir_into(&src, &dst, checksum, on_synced, stats)?;
+        } else {
+            if dst.is_dir() {
+                remove(&dst)?;
+            }
+            if !is_unchanged(&src, &src_meta, &dst, checksum)? {
+                match copy_file_with_mtime(&src, &src_meta, &dst) {
+                    Ok(()) => {
+                        stats.copied_files += 1;
+                        stats.copied_bytes += src_meta.len();
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound && !src.exists() => continue,
+                    Err(e) => {
+                        return Err(anyhow!(e).context(format!(
+                            "cannot copy file from `{}` to `{}`",
+                            src.to_string_lossy(),
+                            dst.to_string_lossy()
+                        )))
+                    }
+                }
+            }
+            on_synced(src_meta.len());
+        }
+        synced.insert(ent.file_name());
+    }
+
+    let entries =
+        fs::read_dir(to).with_context(|| format!("cannot read dir `{}`", to.to_string_lossy()))?;
+    for ent in entries {
+        let ent = ent.with_context(|| format!("cannot read dir `{}`", to.to_string_lossy()))?;
+        if !synced.contains(&ent.file_name()) {
+            remove(&ent.path())?;
+            stats.removed += 1;
+        }
+    }
+    Ok(())
+}
+
+fn is_unchanged(src: &Path, src_meta: &fs::Metadata, dst: &Path, checksum: bool) -> Result<bool> {
+    let dst_meta = match fs::metadata(dst) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => {
+            return Err(anyhow!(e).context(format!("cannot stat `{}`", dst.to_string_lossy())))
+        }
+    };
+    if !dst_meta.is_file() || dst_meta.len() != src_meta.len() {
+        return Ok(false);
+    }
+    if checksum {
+        Ok(sha256_file(src)? == sha256_file(dst)?)
+    } else {
+        Ok(src_meta.modified().ok() == dst_meta.modified().ok())
+    }
+}
+
+// Copy the file and keep its mtime, so the next delta sync knows it's unchanged.
+// `src_meta` is taken before copying, a file modified meanwhile is copied again next time.
+fn copy_file_with_mtime(src: &Path, src_meta: &fs::Metadata, dst: &Path) -> io::Result<()> {
+    fs::copy(src, dst)?;
+    if let Ok(mtime) = src_meta.modified() {
+        File::options().write(true).open(dst)?.set_modified(mtime)?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    let res = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    res.with_context(|| format!("cannot remove `{}`", path.to_string_lossy()))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("cannot open `{}`", path.to_string_lossy()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("cannot read `{}`", path.to_string_lossy()))?;
+    Ok(hex::encode(hasher.finalize()))
+}
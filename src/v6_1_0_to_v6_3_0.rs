@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::cert::CertAndKey;
+use crate::consistency::pick_authoritative_node;
+use crate::migrate::fill_network_tls_certs;
+use crate::migrate::new;
+use crate::network::multiaddr;
+use crate::network::NetworkKind;
+use crate::pipeline::ChainModel;
+use crate::pipeline::Migration;
+use crate::pipeline::NewChain;
+use crate::pipeline::OldChain;
+use crate::pipeline::StepContext;
+use crate::pipeline::Version;
+use crate::self_addr::resolve_self_addrs;
+use crate::self_addr::SelfAddrHints;
+
+// Per service config files to a single `config.toml` with the chosen consensus and network.
+pub struct Step;
+
+impl Migration for Step {
+    fn source(&self) -> Version {
+        Version::V6_1_0
+    }
+
+    fn target(&self) -> Version {
+        Version::V6_3_0
+    }
+
+    fn migrate(&self, chain: ChainModel, ctx: &mut StepContext) -> Result<ChainModel> {
+        let old = match chain {
+            ChainModel::V6_1_0(old) => old,
+            other => bail!("expect a 6.1.0 chain, got {}", other.version()),
+        };
+        Ok(ChainModel::V6_3_0(migrate_chain(old, ctx)?))
+    }
+}
+
+fn migrate_chain(old: OldChain, ctx: &mut StepContext) -> Result<NewChain> {
+    let OldChain {
+        node_dirs,
+        nodes: old_nodes,
+        admin_key_ids,
+    } = old;
+
+    // All nodes must share the same system config and genesis, or the new chain will fork.
+    let authoritative = pick_authoritative_node(
+        &node_dirs,
+        &old_nodes,
+        ctx.opts.authoritative_node.as_deref(),
+    )?;
+
+    // Construct new node config from the old one. (without network_tls info)
+    let mut node_configs: Vec<new::Config> = old_nodes
+        .iter()
+        .map(|n| n.generate_new(ctx.opts.consensus, ctx.opts.network))
+        .collect();
+    if let Some(c) = node_configs.get(authoritative) {
+        let mut system_config = c.system_config.clone();
+        // The old chain only knows validators by node address.
+        if ctx.opts.consensus.needs_validator_keys() {
+            system_config.validators =
+                ctx.opts
+                    .validator_keys
+                    .keys_of(&system_config.validators)
+                    .with_context(|| format!("{} needs validator keys", ctx.opts.consensus))?;
+        }
+        let genesis_block = c.genesis_block.clone();
+        for c in node_configs.iter_mut() {
+            c.system_config = system_config.clone();
+            c.genesis_block = genesis_block.clone();
+        }
+    }
+
+    let host_port_to_addr = fill_self_addrs(&mut node_configs, &ctx.opts.self_addr_hints)
+        .context("cannot find out nodes' own addresses")?;
+    // Fill the network_tls info, other network services need no certs.
+    let ca = if ctx.opts.network.needs_certs() {
+        let ca = ctx.keys.ca_or_generate();
+        fill_network_tls_certs(&mut node_configs, ctx.keys, &host_port_to_addr)
+            .context("cannot fill network_tls info for chain config")?;
+        Some(ca)
+    } else {
+        None
+    };
+
+    // Construct $NEW_CHAIN_DATA_DIR/$CHAIN_NAME/config.toml
+    let meta_config = {
+        let node_addrs: Vec<String> = node_configs
+            .iter()
+            .map(|c| c.controller.node_address.clone())
+            .collect();
+        // Sample node
+        let first_node = node_configs
+            .first()
+            .context("Empty chain. No node config found")?;
+        let system_config = first_node.system_config.clone();
+        let genesis_block = first_node.genesis_block.clone();
+
+        // Every node, the sample one first, with its domain whatever the network service.
+        let peers: Vec<new::NetworkTlsPeerConfig> = {
+            // Network info has been filled.
+            let itself = (
+                first_node
+                    .network_host
+                    .clone()
+                    .context("node's self host not filled")?,
+                first_node
+                    .network_port
+                    .context("node's self port not filled")?,
+            );
+            std::iter::once(itself)
+                .chain(first_node.network.peer_addrs()?)
+                .map(|(host, port)| new::NetworkTlsPeerConfig {
+                    domain: host_port_to_addr.get(&(host.clone(), port)).cloned(),
+                    host,
+                    port,
+                })
+                .collect()
+        };
+        let tls_peers = new::MetaNetworkConfig {
+            peers: peers.clone(),
+        };
+        let network_config = match ctx.opts.network {
+            NetworkKind::Tls => new::MetaNetwork::Tls(tls_peers.clone()),
+            NetworkKind::P2p => new::MetaNetwork::P2p(new::MetaNetworkP2pConfig {
+                peers: peers
+                    .iter()
+                    .map(|p| new::NetworkP2pPeerConfig {
+                        address: multiaddr(&p.host, p.port),
+                    })
+                    .collect(),
+            }),
+        };
+
+        let current_config = {
+            let (ips, p2p_ports) = peers.iter().map(|p| (p.host.clone(), p.port)).unzip();
+
+            let rpc_ports = node_configs
+                .iter()
+                .map(|c| c.controller.controller_port)
+                .collect();
+
+            let (ca_cert_pem, ca_key_pem) = match ca {
+                Some(CertAndKey { cert, key }) => (Some(cert), Some(key)),
+                None => (None, None),
+            };
+            new::MetaCurrentConfig {
+                addresses: node_addrs,
+                ca_cert_pem,
+                ca_key_pem,
+                count: node_configs.len() as u64,
+
+                ips,
+                p2p_ports,
+                rpc_ports,
+
+                use_num: false,
+                tls_peers: ctx.opts.network.needs_certs().then_some(tls_peers),
+            }
+        };
+
+        let admin_config = {
+            // Only the admin of the chosen system config, the others may not have a key.
+            let admin_address = first_node.system_config.admin.clone();
+            let key_id = *admin_key_ids.get(&admin_address).with_context(|| {
+                format!(
+                    "no `key_id` of admin `{}` found in the metadata dir",
+                    admin_address
+                )
+            })?;
+            new::MetaAdminConfig {
+                admin_address,
+                key_id,
+            }
+        };
+
+        new::MetaConfig {
+            network: network_config,
+            genesis_block,
+            system_config,
+            admin_config,
+            current_config,
+        }
+    };
+
+    Ok(NewChain {
+        node_configs,
+        meta_config,
+    })
+}
+
+// Fill every node's own host and port, and return the (host, port) -> node_addr map.
+fn fill_self_addrs(
+    node_configs: &mut [new::Config],
+    self_addr_hints: &SelfAddrHints,
+) -> Result<HashMap<(String, u16), String>> {
+    let self_addrs = resolve_self_addrs(node_configs, self_addr_hints)?;
+
+    Ok(node_configs
+        .iter_mut()
+        .zip(self_addrs)
+        .map(|(c, (host, port))| {
+            c.network_host.replace(host.clone());
+            c.network_port.replace(port);
+            ((host, port), c.controller.node_address.clone())
+        })
+        .collect())
+}
@@ -0,0 +1,177 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::ensure;
+use anyhow::Result;
+
+use crate::consensus::ConsensusKind;
+use crate::consistency::diff_nodes;
+use crate::detect::detect_chain;
+use crate::migrate::extract_text;
+use crate::migrate::load_node_dirs;
+use crate::migrate::NodeConfigMigrate;
+use crate::migrate::NodeDirs;
+use crate::migrate::CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS;
+use crate::migrate::LOG4RS_AND_KMS_DB_FILES;
+use crate::network::NetworkKind;
+use crate::pipeline::Version;
+use crate::running::check_running;
+
+// Check the old chain dir without writing anything, and report every problem found.
+pub fn validate<P: AsRef<Path>>(
+    chain_data_dir: P,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+) -> Result<()> {
+    let problems = check_chain(chain_data_dir.as_ref(), chain_name, explicit_node_dirs);
+    for p in &problems {
+        println!("{}", p);
+    }
+    ensure!(
+        problems.is_empty(),
+        "{} problem(s) found in the old chain",
+        problems.len()
+    );
+    Ok(())
+}
+
+fn check_chain(
+    chain_data_dir: &Path,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+) -> Vec<String> {
+    let chain_metadata_dir = chain_data_dir.join(chain_name);
+    if !chain_data_dir.is_dir() {
+        return vec![format!(
+            "chain data folder `{}` not found",
+            chain_data_dir.to_string_lossy()
+        )];
+    }
+
+    let mut problems = vec![];
+    // Nothing else makes sense for a chain of another version, but a node dir
+    // that can't be told apart is checked below like the others.
+    match detect_chain(chain_data_dir, chain_name, explicit_node_dirs) {
+        Ok(detected) if detected.version == Version::V6_1_0 => {
+            println!("detected version: {}", detected)
+        }
+        Ok(detected) => {
+            return vec![format!(
+                "the chain is at {}, only 6.1.0 chains can be migrated, detected from {}",
+                detected.version, detected.evidence
+            )]
+        }
+        Err(e) => problems.push(format!("cannot detect the version of the chain: {:#}", e)),
+    }
+
+    if !chain_metadata_dir.is_dir() {
+        problems.push(format!(
+            "metadata folder `{}` not found",
+            chain_metadata_dir.to_string_lossy()
+        ));
+    }
+
+    let node_dirs = match load_node_dirs(chain_data_dir, chain_name, explicit_node_dirs) {
+        Ok(NodeDirs { dirs, skipped }) => {
+            for (d, reason) in skipped {
+                println!("warning: skip dir `{}`: {}", d.to_string_lossy(), reason);
+            }
+            dirs
+        }
+        Err(e) => {
+            problems.push(format!("cannot load node dirs: {:#}", e));
+            vec![]
+        }
+    };
+    if node_dirs.is_empty() {
+        problems.push("empty chain, no node dir found".to_string());
+    }
+
+    let mut admins = vec![];
+    let mut extracted = vec![];
+    for node_dir in &node_dirs {
+        let node_problems = check_node(node_dir);
+        problems.extend(
+            node_problems
+                .into_iter()
+                .map(|p| format!("node `{}`: {}", node_dir.to_string_lossy(), p)),
+        );
+
+        if let Ok(node) = NodeConfigMigrate::extract_from(node_dir) {
+            if !admins.contains(&node.system_config.admin) {
+                admins.push(node.system_config.admin.clone());
+            }
+            extracted.push((node_dir.clone(), node));
+        }
+    }
+
+    let (extracted_dirs, extracted_nodes): (Vec<_>, Vec<_>) = extracted.into_iter().unzip();
+    let diff = diff_nodes(&extracted_dirs, &extracted_nodes);
+    if !diff.is_empty() {
+        problems.push(format!(
+            "nodes disagree on system config or genesis:\n{}",
+            diff.trim_end()
+        ));
+    }
+
+    // The admin dir lives under the metadata folder, named by the admin address.
+    if chain_metadata_dir.is_dir() {
+        for admin in admins {
+            let admin_key_dir = chain_metadata_dir.join(&admin);
+            if !admin_key_dir.is_dir() {
+                problems.push(format!(
+                    "admin dir `{}` not found",
+                    admin_key_dir.to_string_lossy()
+                ));
+                continue;
+            }
+            match extract_text(&admin_key_dir, "key_id") {
+                Ok(key_id) if key_id.parse::<u64>().is_err() => problems.push(format!(
+                    "invalid admin `key_id` `{}` in `{}`",
+                    key_id,
+                    admin_key_dir.to_string_lossy()
+                )),
+                Ok(_) => (),
+                Err(e) => problems.push(format!("cannot load admin `key_id`: {:#}", e)),
+            }
+        }
+    }
+
+    problems
+}
+
+fn check_node(node_dir: &Path) -> Vec<String> {
+    let mut problems = vec![];
+
+    let missing_configs: Vec<&str> = NodeConfigMigrate::CONFIG_FILES
+        .iter()
+        .copied()
+        .filter(|f| !node_dir.join(f).is_file())
+        .collect();
+    for f in &missing_configs {
+        problems.push(format!("missing file `{}`", f));
+    }
+    // Only parse configs when they are all present, otherwise it just repeats the missing ones.
+    if missing_configs.is_empty() {
+        match NodeConfigMigrate::extract_from(node_dir) {
+            Ok(node) => problems.extend(check_running(
+                node_dir,
+                &node.generate_new(ConsensusKind::default(), NetworkKind::default()),
+            )),
+            Err(e) => problems.push(format!("invalid node config: {:#}", e)),
+        }
+    }
+
+    for f in LOG4RS_AND_KMS_DB_FILES {
+        if !node_dir.join(f).is_file() {
+            problems.push(format!("missing file `{}`", f));
+        }
+    }
+    for d in CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS {
+        if !node_dir.join(d).is_dir() {
+            problems.push(format!("missing dir `{}`", d));
+        }
+    }
+
+    problems
+}
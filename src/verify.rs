@@ -0,0 +1,371 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::consensus::ConsensusKind;
+use crate::consensus::ValidatorKeys;
+use crate::consistency::pick_authoritative_node;
+use crate::detect::ensure_v6_1_0;
+use crate::migrate::load_node_dirs;
+use crate::migrate::new;
+use crate::migrate::new_node_dir;
+use crate::migrate::NodeConfigMigrate;
+use crate::migrate::NodeDirs;
+use crate::migrate::CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS;
+use crate::migrate::LOG4RS_AND_KMS_DB_FILES;
+use crate::network::NetworkKind;
+use crate::transfer::sha256_file;
+
+// Check the migrated chain against the old one, and report every difference found.
+pub fn verify<P, Q>(
+    chain_data_dir: P,
+    new_chain_data_dir: Q,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+    authoritative_node: Option<&str>,
+    validator_keys: &ValidatorKeys,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let problems = check_chain(
+        chain_data_dir.as_ref(),
+        new_chain_data_dir.as_ref(),
+        chain_name,
+        explicit_node_dirs,
+        authoritative_node,
+        validator_keys,
+    )?;
+    for p in &problems {
+        println!("{}", p);
+    }
+    ensure!(
+        problems.is_empty(),
+        "{} problem(s) found in the new chain",
+        problems.len()
+    );
+    Ok(())
+}
+
+fn check_chain(
+    chain_data_dir: &Path,
+    new_chain_data_dir: &Path,
+    chain_name: &str,
+    explicit_node_dirs: &[PathBuf],
+    authoritative_node: Option<&str>,
+    validator_keys: &ValidatorKeys,
+) -> Result<Vec<String>> {
+    // The old chain is the reference, it must be readable.
+    ensure_v6_1_0(chain_data_dir, chain_name, explicit_node_dirs)?;
+    let NodeDirs { dirs, skipped } =
+        load_node_dirs(chain_data_dir, chain_name, explicit_node_dirs)?;
+    for (d, reason) in skipped {
+        println!("warning: skip dir `{}`: {}", d.to_string_lossy(), reason);
+    }
+    ensure!(!dirs.is_empty(), "Empty chain. No node dir found");
+    let old_nodes = dirs
+        .iter()
+        .map(|d| {
+            NodeConfigMigrate::extract_from(d).with_context(|| {
+                format!(
+                    "cannot extract info from old node config in `{}`",
+                    d.to_string_lossy()
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let authoritative = pick_authoritative_node(&dirs, &old_nodes, authoritative_node)?;
+    let new::Config {
+        system_config: expected_system_config,
+        genesis_block: expected_genesis_block,
+        ..
+    } = old_nodes[authoritative].generate_new(ConsensusKind::default(), NetworkKind::default());
+
+    let mut problems = vec![];
+
+    // meta
+    let new_chain_metadata_dir = new_chain_data_dir.join(chain_name);
+    let meta_config =
+        match load_toml::<new::MetaConfig>(&new_chain_metadata_dir) {
+            Ok(mut meta_config) => {
+                meta_config.system_config.validators =
+                    validator_keys.addresses_of(&meta_config.system_config.validators);
+                let meta_problems = check_meta_config(
+                    &meta_config,
+                    &old_nodes,
+                    &expected_system_config,
+                    &expected_genesis_block,
+                );
+                problems.extend(meta_problems.into_iter().map(|p| {
+                    format!("meta `{}`: {}", new_chain_metadata_dir.to_string_lossy(), p)
+                }));
+                Some(meta_config)
+            }
+            Err(e) => {
+                problems.push(format!("{:#}", e));
+                None
+            }
+        };
+    for f in LOG4RS_AND_KMS_DB_FILES {
+        problems.extend(compare_file(
+            &dirs[0].join(f),
+            &new_chain_metadata_dir.join(f),
+        ));
+    }
+
+    // nodes
+    for (old_node_dir, old_node) in dirs.iter().zip(&old_nodes) {
+        let mut expected = old_node.generate_new(ConsensusKind::default(), NetworkKind::default());
+        expected.system_config = expected_system_config.clone();
+        expected.genesis_block = expected_genesis_block.clone();
+
+        let node_problems = check_node(
+            old_node_dir,
+            new_chain_data_dir,
+            chain_name,
+            expected,
+            meta_config.as_ref(),
+            validator_keys,
+        );
+        problems.extend(
+            node_problems
+                .into_iter()
+                .map(|p| format!("node `{}`: {}", old_node_dir.to_string_lossy(), p)),
+        );
+    }
+
+    Ok(problems)
+}
+
+fn check_meta_config(
+    meta_config: &new::MetaConfig,
+    old_nodes: &[NodeConfigMigrate],
+    expected_system_config: &new::SystemConfig,
+    expected_genesis_block: &new::GenesisBlock,
+) -> Vec<String> {
+    let mut problems = vec![];
+    diff_values(
+        "system_config",
+        &to_value(expected_system_config),
+        &to_value(&meta_config.system_config),
+        &mut problems,
+    );
+    diff_values(
+        "genesis_block",
+        &to_value(expected_genesis_block),
+        &to_value(&meta_config.genesis_block),
+        &mut problems,
+    );
+
+    let expected_addrs: BTreeSet<&str> = old_nodes.iter().map(|n| n.node_addr.as_str()).collect();
+    let found_addrs: BTreeSet<&str> = meta_config
+        .current_config
+        .addresses
+        .iter()
+        .map(String::as_str)
+        .collect();
+    if expected_addrs != found_addrs {
+        problems.push(format!(
+            "`current_config.addresses`: expected {:?}, found {:?}",
+            expected_addrs, found_addrs
+        ));
+    }
+    problems
+}
+
+fn check_node(
+    old_node_dir: &Path,
+    new_chain_data_dir: &Path,
+    chain_name: &str,
+    mut expected: new::Config,
+    meta_config: Option<&new::MetaConfig>,
+    validator_keys: &ValidatorKeys,
+) -> Vec<String> {
+    let new_node_dir = match new_node_dir(
+        new_chain_data_dir,
+        chain_name,
+        &expected.controller.node_address,
+    ) {
+        Ok(d) => d,
+        Err(e) => return vec![format!("{:#}", e)],
+    };
+    if !new_node_dir.is_dir() {
+        return vec![format!(
+            "new node dir `{}` not found",
+            new_node_dir.to_string_lossy()
+        )];
+    }
+
+    let mut problems = vec![];
+    match load_toml::<new::Config>(&new_node_dir) {
+        Ok(mut found) => {
+            // Validators may be keys, the old chain knows them by address.
+            found.system_config.validators =
+                validator_keys.addresses_of(&found.system_config.validators);
+            // Any consensus may have been chosen, but its ports and address must be carried over.
+            expected.consensus = found.consensus.kind().generate(&expected.controller);
+            // So may any network, with the same ports and peers.
+            match expected.network.peer_addrs() {
+                Ok(peers) => {
+                    expected.network = found.network.kind().generate(
+                        expected.network.grpc_port(),
+                        expected.network.listen_port(),
+                        &peers,
+                    )
+                }
+                Err(e) => problems.push(format!("{:#}", e)),
+            }
+            if let new::Network::Tls(network) = &found.network {
+                if let Some(meta_config) = meta_config {
+                    let ca_cert = &meta_config.current_config.ca_cert_pem;
+                    if ca_cert.is_none() || network.ca_cert != *ca_cert {
+                        problems.push("`network_tls.ca_cert` is not the chain's CA".to_string());
+                    }
+                }
+                if network.cert.is_none() {
+                    problems.push("`network_tls.cert` is missing".to_string());
+                }
+            }
+            // Certs and peer domains are generated, the rest must be carried over.
+            let mut found = to_value(&found);
+            if let Some(network) = found.get_mut("network_tls").and_then(|v| v.as_table_mut()) {
+                network.remove("ca_cert");
+                network.remove("cert");
+                if let Some(peers) = network.get_mut("peers").and_then(|v| v.as_array_mut()) {
+                    for p in peers.iter_mut().filter_map(|p| p.as_table_mut()) {
+                        p.remove("domain");
+                    }
+                }
+            }
+            diff_values("", &to_value(&expected), &found, &mut problems);
+        }
+        Err(e) => problems.push(format!("{:#}", e)),
+    }
+
+    for f in LOG4RS_AND_KMS_DB_FILES {
+        problems.extend(compare_file(&old_node_dir.join(f), &new_node_dir.join(f)));
+    }
+    for d in CHAIN_DATA_AND_STORAGE_DATA_AND_LOGS_DIRS {
+        compare_dir(&old_node_dir.join(d), &new_node_dir.join(d), &mut problems);
+    }
+
+    problems
+}
+
+fn load_toml<T: DeserializeOwned>(dir: &Path) -> Result<T> {
+    let path = dir.join("config.toml");
+    let s = fs::read_to_string(&path)
+        .with_context(|| format!("cannot read `{}`", path.to_string_lossy()))?;
+    toml::from_str(&s).with_context(|| format!("invalid `{}`", path.to_string_lossy()))
+}
+
+fn to_value<T: Serialize>(v: &T) -> toml::Value {
+    // Our configs are always representable in toml.
+    toml::Value::try_from(v).unwrap()
+}
+
+fn diff_values(path: &str, expected: &toml::Value, found: &toml::Value, out: &mut Vec<String>) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (expected, found) {
+        (toml::Value::Table(expected), toml::Value::Table(found)) => {
+            for (key, e) in expected {
+                match found.get(key) {
+                    Some(f) => diff_values(&join(key), e, f, out),
+                    None => out.push(format!("missing `{}`", join(key))),
+                }
+            }
+            for key in found.keys().filter(|k| !expected.contains_key(*k)) {
+                out.push(format!("unexpected `{}`", join(key)));
+            }
+        }
+        (expected, found) if expected != found => out.push(format!(
+            "`{}`: expected `{}`, found `{}`",
+            path, expected, found
+        )),
+        _ => (),
+    }
+}
+
+fn compare_file(old: &Path, new: &Path) -> Option<String> {
+    if !old.is_file() {
+        return Some(format!(
+            "source file `{}` not found, cannot verify",
+            old.to_string_lossy()
+        ));
+    }
+    if !new.is_file() {
+        return Some(format!("file `{}` not found", new.to_string_lossy()));
+    }
+    let size = |p: &Path| fs::metadata(p).map(|m| m.len()).ok();
+    if size(old) != size(new) {
+        return Some(format!(
+            "file `{}` differs in size from `{}`",
+            new.to_string_lossy(),
+            old.to_string_lossy()
+        ));
+    }
+    match (sha256_file(old), sha256_file(new)) {
+        (Ok(a), Ok(b)) if a == b => None,
+        (Ok(_), Ok(_)) => Some(format!(
+            "file `{}` differs in content from `{}`",
+            new.to_string_lossy(),
+            old.to_string_lossy()
+        )),
+        (Err(e), _) | (_, Err(e)) => Some(format!("{:#}", e)),
+    }
+}
+
+fn compare_dir(old: &Path, new: &Path, problems: &mut Vec<String>) {
+    if !old.is_dir() {
+        // e.g. moved by `--data-mode move`.
+        problems.push(format!(
+            "source dir `{}` not found, cannot verify",
+            old.to_string_lossy()
+        ));
+        return;
+    }
+    if !new.is_dir() {
+        problems.push(format!("dir `{}` not found", new.to_string_lossy()));
+        return;
+    }
+
+    let names = |d: &Path| -> Result<BTreeSet<_>> {
+        fs::read_dir(d)
+            .with_context(|| format!("cannot read dir `{}`", d.to_string_lossy()))?
+            .map(|ent| Ok(ent?.file_name()))
+            .collect()
+    };
+    let (old_names, new_names) = match (names(old), names(new)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            problems.push(format!("{:#}", e));
+            return;
+        }
+    };
+
+    for name in &old_names {
+        let (old, new) = (old.join(name), new.join(name));
+        if old.is_dir() {
+            compare_dir(&old, &new, problems);
+        } else {
+            problems.extend(compare_file(&old, &new));
+        }
+    }
+    for name in new_names.difference(&old_names) {
+        problems.push(format!("unexpected `{}`", new.join(name).to_string_lossy()));
+    }
+}